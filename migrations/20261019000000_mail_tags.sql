@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE mail_address ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE tag_role (
+    guild_id BIGINT NOT NULL REFERENCES email_verify(guild_id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    role_id BIGINT NOT NULL,
+    PRIMARY KEY (guild_id, tag, role_id)
+);
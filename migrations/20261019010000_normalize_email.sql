@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE email_verify ADD COLUMN lowercase_local_part BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE email_verify ADD COLUMN strip_plus_tag BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE email_verify ADD COLUMN ignore_gmail_dots BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE mail_address ADD COLUMN normalized_email TEXT NOT NULL DEFAULT '';
+CREATE INDEX mail_address_normalized_email_idx ON mail_address (guild_id, normalized_email);
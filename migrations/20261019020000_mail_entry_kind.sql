@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE mail_address ADD COLUMN kind TEXT NOT NULL DEFAULT 'address';
+ALTER TABLE mail_address ADD COLUMN domain TEXT NOT NULL DEFAULT '';
+ALTER TABLE mail_address ADD COLUMN local_pattern TEXT NOT NULL DEFAULT '';
+CREATE INDEX mail_address_domain_idx ON mail_address (guild_id, domain);
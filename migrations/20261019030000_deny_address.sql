@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE deny_address (
+    guild_id BIGINT NOT NULL REFERENCES email_verify(guild_id) ON DELETE CASCADE,
+    id SERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    normalized_email TEXT NOT NULL DEFAULT '',
+    domain TEXT NOT NULL DEFAULT '',
+    local_pattern TEXT NOT NULL DEFAULT ''
+);
+CREATE INDEX deny_address_domain_idx ON deny_address (guild_id, domain);
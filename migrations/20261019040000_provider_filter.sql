@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE email_verify ADD COLUMN reject_disposable BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE email_verify ADD COLUMN reject_free_mail BOOLEAN NOT NULL DEFAULT FALSE;
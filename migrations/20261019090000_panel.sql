@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE panel (
+    id SERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL REFERENCES email_verify(guild_id) ON DELETE CASCADE,
+    name TEXT NOT NULL DEFAULT '',
+    email_pattern TEXT NOT NULL,
+    pattern_form TEXT,
+    role_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    enable_check_mail BOOLEAN NOT NULL DEFAULT FALSE,
+    content TEXT NOT NULL DEFAULT '{}',
+    message_channel_id BIGINT,
+    message_id BIGINT,
+    message_thread_id BIGINT,
+    settings_version BIGINT NOT NULL DEFAULT 0,
+    broken_reason TEXT
+);
+CREATE INDEX panel_guild_id_idx ON panel (guild_id);
+
+-- Panels set up before texts were configurable were always Japanese.
+INSERT INTO panel (guild_id, email_pattern, role_id, channel_id, enable_check_mail, content)
+SELECT guild_id, email_pattern, role_id, channel_id, enable_check_mail, '{"locale":"ja"}'
+FROM email_verify;
+
+ALTER TABLE email_verify
+    DROP COLUMN email_pattern,
+    DROP COLUMN role_id,
+    DROP COLUMN channel_id,
+    DROP COLUMN enable_check_mail;
@@ -0,0 +1,44 @@
+-- Add migration script here
+-- Normalization and provider filters belong to a panel, existing guilds keep
+-- their options on their only panel.
+ALTER TABLE panel
+    ADD COLUMN lowercase_local_part BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN strip_plus_tag BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN ignore_gmail_dots BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN reject_disposable BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN reject_free_mail BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE panel SET
+    lowercase_local_part = email_verify.lowercase_local_part,
+    strip_plus_tag = email_verify.strip_plus_tag,
+    ignore_gmail_dots = email_verify.ignore_gmail_dots,
+    reject_disposable = email_verify.reject_disposable,
+    reject_free_mail = email_verify.reject_free_mail
+FROM email_verify WHERE email_verify.guild_id = panel.guild_id;
+ALTER TABLE email_verify
+    DROP COLUMN lowercase_local_part,
+    DROP COLUMN strip_plus_tag,
+    DROP COLUMN ignore_gmail_dots,
+    DROP COLUMN reject_disposable,
+    DROP COLUMN reject_free_mail;
+
+-- Lists belong to a panel, existing entries to the guild's only panel.
+ALTER TABLE mail_address ADD COLUMN panel_id INTEGER REFERENCES panel(id) ON DELETE CASCADE;
+UPDATE mail_address SET panel_id = panel.id FROM panel WHERE panel.guild_id = mail_address.guild_id;
+ALTER TABLE mail_address ALTER COLUMN panel_id SET NOT NULL;
+DROP INDEX mail_address_normalized_email_idx;
+DROP INDEX mail_address_domain_idx;
+CREATE INDEX mail_address_normalized_email_idx ON mail_address (panel_id, normalized_email);
+CREATE INDEX mail_address_domain_idx ON mail_address (panel_id, domain);
+
+ALTER TABLE deny_address ADD COLUMN panel_id INTEGER REFERENCES panel(id) ON DELETE CASCADE;
+UPDATE deny_address SET panel_id = panel.id FROM panel WHERE panel.guild_id = deny_address.guild_id;
+ALTER TABLE deny_address ALTER COLUMN panel_id SET NOT NULL;
+DROP INDEX deny_address_domain_idx;
+CREATE INDEX deny_address_normalized_email_idx ON deny_address (panel_id, normalized_email);
+CREATE INDEX deny_address_domain_idx ON deny_address (panel_id, domain);
+
+ALTER TABLE tag_role ADD COLUMN panel_id INTEGER REFERENCES panel(id) ON DELETE CASCADE;
+UPDATE tag_role SET panel_id = panel.id FROM panel WHERE panel.guild_id = tag_role.guild_id;
+ALTER TABLE tag_role ALTER COLUMN panel_id SET NOT NULL;
+ALTER TABLE tag_role DROP CONSTRAINT tag_role_pkey;
+ALTER TABLE tag_role ADD PRIMARY KEY (panel_id, tag, role_id);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE email_verify ADD COLUMN log_channel_id BIGINT;
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- panel_id isn't a foreign key: the verification keeps its address bound after the panel is deleted.
+CREATE TABLE verification (
+    guild_id BIGINT NOT NULL REFERENCES email_verify(guild_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL,
+    panel_id INTEGER NOT NULL,
+    email TEXT NOT NULL,
+    normalized_email TEXT NOT NULL,
+    role_ids BIGINT[] NOT NULL DEFAULT '{}',
+    verified_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    left_at TIMESTAMPTZ,
+    released BOOLEAN NOT NULL DEFAULT false,
+    PRIMARY KEY (guild_id, user_id, panel_id)
+);
+CREATE INDEX verification_email_idx ON verification (guild_id, normalized_email);
+
+ALTER TABLE email_verify ADD COLUMN member_leave_action TEXT NOT NULL DEFAULT 'keep';
+ALTER TABLE email_verify ADD COLUMN one_address_per_account BOOLEAN NOT NULL DEFAULT false;
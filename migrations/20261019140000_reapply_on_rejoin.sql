@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE email_verify ADD COLUMN reapply_on_rejoin BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Whether a list entry (mail_address or deny_address) matches a normalized address.
+-- Arguments: kind, normalized_email, domain, local_pattern, address.
+CREATE FUNCTION mail_entry_matches(TEXT, TEXT, TEXT, TEXT, TEXT) RETURNS BOOLEAN
+LANGUAGE sql IMMUTABLE
+AS $$
+    SELECT CASE $1
+        WHEN 'address' THEN $2 = $5
+        WHEN 'domain' THEN $3 = substring($5 FROM '@([^@]*)$')
+        WHEN 'subdomain' THEN right(substring($5 FROM '@([^@]*)$'), length($3) + 1) = '.' || $3
+        WHEN 'glob' THEN $3 = substring($5 FROM '@([^@]*)$')
+            AND regexp_replace($5, '@[^@]*$', '') LIKE $4
+        ELSE FALSE
+    END
+$$;
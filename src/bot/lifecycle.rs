@@ -0,0 +1,205 @@
+use crate::db::panel as panel_db;
+use crate::db::tag_role as tag_db;
+use crate::db::verification::{self as verification_db, MemberLeaveAction, Verification};
+use crate::db::verify as verify_db;
+use crate::utils::notify;
+use crate::utils::roles;
+use crate::utils::rules::{Rules, Verdict};
+use crate::utils::state::AppState;
+
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker};
+use twilight_model::id::Id;
+use twilight_model::util::Timestamp;
+
+/// Disables the panels granting a deleted role and drops it from tag roles.
+pub async fn role_deleted(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    role_id: Id<RoleMarker>,
+) -> anyhow::Result<()> {
+    let guild_id = guild_id.get() as i64;
+    let role_id = role_id.get() as i64;
+    let panels = panel_db::mark_broken_by_role(
+        &state.pool,
+        guild_id,
+        role_id,
+        &format!("Role {} was deleted", role_id),
+    )
+    .await?;
+    let tags = tag_db::delete_role(&state.pool, guild_id, role_id).await?;
+    if panels.is_empty() && tags.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Role {} of {} was deleted", role_id, guild_id);
+    let mut content = format!(
+        "The role {} used for email verification was deleted.",
+        role_id
+    );
+    for name in panels {
+        content.push_str(&format!(
+            "\n- Panel \"{}\" is disabled until a new role is chosen in the dashboard.",
+            name
+        ));
+    }
+    for tag in tags {
+        content.push_str(&format!(
+            "\n- It is no longer granted for the tag \"{}\".",
+            tag
+        ));
+    }
+    notify::notify_admins(state, guild_id, &content).await;
+
+    Ok(())
+}
+
+/// Disables the panels posted in a deleted channel and unsets it as log channel.
+pub async fn channel_deleted(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    channel_id: Id<ChannelMarker>,
+) -> anyhow::Result<()> {
+    let guild_id = guild_id.get() as i64;
+    let channel_id = channel_id.get() as i64;
+    verify_db::clear_log_channel(&state.pool, guild_id, channel_id).await?;
+    let panels = panel_db::mark_broken_by_channel(
+        &state.pool,
+        guild_id,
+        channel_id,
+        &format!("Channel {} was deleted", channel_id),
+    )
+    .await?;
+    if panels.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Panel channel {} of {} was deleted", channel_id, guild_id);
+    let mut content = format!(
+        "The channel {} holding email verification panels was deleted.",
+        channel_id
+    );
+    for name in panels {
+        content.push_str(&format!(
+            "\n- Panel \"{}\" is disabled until a new channel is chosen in the dashboard.",
+            name
+        ));
+    }
+    notify::notify_admins(state, guild_id, &content).await;
+
+    Ok(())
+}
+
+/// Restores the data of a guild the bot was re-added to within the retention window.
+pub async fn guild_created(state: &AppState, guild_id: Id<GuildMarker>) -> anyhow::Result<()> {
+    if verify_db::mark_guild_joined(&state.pool, guild_id.get() as i64).await? {
+        tracing::info!("Restore data of {}", guild_id);
+    }
+    Ok(())
+}
+
+/// Marks a guild the bot was removed from, its data is purged later.
+pub async fn guild_deleted(state: &AppState, guild_id: Id<GuildMarker>) -> anyhow::Result<()> {
+    verify_db::mark_guild_left(&state.pool, guild_id.get() as i64).await?;
+    tracing::info!("Removed from {}", guild_id);
+    Ok(())
+}
+
+/// Applies the guild's member leave action to the member's verifications.
+pub async fn member_removed(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> anyhow::Result<()> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    match verification_db::get_member_leave_action(&state.pool, guild_id).await? {
+        MemberLeaveAction::Keep => {
+            verification_db::mark_member_left(&state.pool, guild_id, user_id, false).await?
+        }
+        MemberLeaveAction::Release => {
+            verification_db::mark_member_left(&state.pool, guild_id, user_id, true).await?
+        }
+        MemberLeaveAction::Delete => {
+            verification_db::delete_member_verifications(&state.pool, guild_id, user_id).await?
+        }
+    }
+    Ok(())
+}
+
+/// Gives a rejoining member the roles of their stored verifications back, if the
+/// guild allows it and their address still passes the panel's current rules.
+pub async fn member_added(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    joined_at: Timestamp,
+) -> anyhow::Result<()> {
+    let guild_id = guild_id.get() as i64;
+    let user_id = user_id.get() as i64;
+    if !verification_db::get_reapply_on_rejoin(&state.pool, guild_id).await? {
+        return Ok(());
+    }
+    let one_per_account =
+        verification_db::get_one_address_per_account(&state.pool, guild_id).await?;
+    for verification in
+        verification_db::get_member_verifications(&state.pool, guild_id, user_id).await?
+    {
+        let Some(panel) = panel_db::get_panel(&state.pool, guild_id, verification.panel_id).await?
+        else {
+            continue;
+        };
+        if panel.broken_reason.is_some() {
+            continue;
+        }
+        let rules = Rules::load(state, &panel).await?;
+        let (normalized_email, tags) = match rules.evaluate(state, &verification.email).await? {
+            Verdict::Accepted {
+                normalized_email,
+                tags,
+                ..
+            } => (normalized_email, tags),
+            verdict => {
+                tracing::info!(
+                    "Not reapplying panel {} to {}: {}",
+                    panel.id,
+                    verification.user_id,
+                    verdict.reason()
+                );
+                continue;
+            }
+        };
+        let verification = Verification {
+            normalized_email,
+            role_ids: roles::verified_role_ids(state, &panel, &tags).await?,
+            ..verification
+        };
+        if let Some(owner_id) =
+            verification_db::record_verification(&state.pool, &verification, one_per_account)
+                .await?
+        {
+            tracing::info!(
+                "Not reapplying panel {} to {}: address is bound to {}",
+                panel.id,
+                user_id,
+                owner_id
+            );
+            continue;
+        }
+        roles::grant_roles(
+            state,
+            guild_id,
+            user_id,
+            &verification.role_ids,
+            "Rejoined after verifying email",
+            &format!(
+                "rejoin:{}:{}:{}:{}",
+                guild_id,
+                user_id,
+                panel.id,
+                joined_at.as_secs()
+            ),
+        )
+        .await?;
+    }
+    Ok(())
+}
@@ -1,3 +1,7 @@
+use crate::db::panel as panel_db;
+use crate::utils::auth_state;
+use crate::utils::panel::{self, PanelContent};
+use crate::utils::rate_limit::{self, BUTTON_PER_GUILD, BUTTON_PER_USER};
 use crate::utils::state::AppState;
 
 use std::env;
@@ -5,100 +9,285 @@ use std::sync::Arc;
 
 use bb8_redis::redis::AsyncCommands;
 use once_cell::sync::Lazy;
-use twilight_gateway::{Event, Intents, Shard, ShardId};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use twilight_gateway::{stream, Config, Event, Intents, Shard};
 use twilight_model::application::interaction::{Interaction, InteractionData, InteractionType};
 use twilight_model::channel::message::component::{ActionRow, Button, ButtonStyle, Component};
 use twilight_model::channel::message::MessageFlags;
 use twilight_model::http::interaction::{
     InteractionResponse, InteractionResponseData, InteractionResponseType,
 };
-use url::Url;
-use uuid::Uuid;
 
-static BASE_AUTH_URL: Lazy<String> =
-    Lazy::new(|| format!("{}/auth", env::var("BASE_URL").unwrap()));
+mod lifecycle;
+
+static SHARD_TOTAL: Lazy<Option<u64>> = Lazy::new(|| {
+    env::var("SHARD_TOTAL")
+        .ok()
+        .and_then(|total| total.trim().parse().ok())
+});
+static SHARD_RANGE: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("SHARD_RANGE")
+        .ok()
+        .filter(|range| !range.is_empty())
+});
+/// Whether the gateway subscribes to member joins and leaves. The intent is
+/// privileged, so member leave actions and rejoin reapply are off without it.
+pub static MEMBER_EVENTS: Lazy<bool> =
+    Lazy::new(|| env::var("ENABLE_MEMBER_EVENTS").is_ok_and(|enable| enable == "true"));
+
+/// Builds the response to a panel button click. Returns `None` for interactions
+/// that aren't for this bot. Shared by the gateway and the HTTP interactions endpoint.
+pub async fn handle_interaction(
+    state: &AppState,
+    interaction: &Interaction,
+) -> anyhow::Result<Option<InteractionResponse>> {
+    if interaction.kind != InteractionType::MessageComponent {
+        return Ok(None);
+    }
+    let Some(InteractionData::MessageComponent(data)) = &interaction.data else {
+        return Ok(None);
+    };
+    let Some(panel_id) = panel::parse_custom_id(&data.custom_id) else {
+        return Ok(None);
+    };
+    let (Some(guild_id), Some(user)) = (
+        interaction.guild_id,
+        interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.user.as_ref()),
+    ) else {
+        return Ok(None);
+    };
+    let guild_id = guild_id.get() as i64;
+    let panel = match panel_id {
+        Some(panel_id) => panel_db::get_panel(&state.pool, guild_id, panel_id).await?,
+        None => panel_db::get_default_panel(&state.pool, guild_id).await?,
+    };
+    let Some(panel) = panel else {
+        tracing::warn!("Panel {:?} of {} not found", panel_id, guild_id);
+        return Ok(None);
+    };
+    let texts =
+        PanelContent::from_panel(&panel)?.texts(interaction.locale.as_deref().unwrap_or_default());
+    if panel.broken_reason.is_some() {
+        return Ok(Some(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseData {
+                content: Some(texts.failure_message),
+                flags: Some(MessageFlags::EPHEMERAL),
+                ..Default::default()
+            }),
+        }));
+    }
+    let limits = [
+        (&BUTTON_PER_USER, user.id.to_string()),
+        (&BUTTON_PER_GUILD, guild_id.to_string()),
+    ];
+    if let Some(retry_after) = rate_limit::hit_all(state, &limits).await? {
+        return Ok(Some(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseData {
+                content: Some(texts.cooldown_message(retry_after)),
+                flags: Some(MessageFlags::EPHEMERAL),
+                ..Default::default()
+            }),
+        }));
+    }
+    let url = auth_state::create(state, user.id.get(), guild_id, panel.id).await?;
+
+    Ok(Some(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(InteractionResponseData {
+            content: Some(texts.reply_message),
+            flags: Some(MessageFlags::EPHEMERAL),
+            components: Some(vec![Component::ActionRow(ActionRow {
+                components: vec![Component::Button(Button {
+                    style: ButtonStyle::Link,
+                    label: Some(texts.link_label),
+                    custom_id: None,
+                    url: Some(url.to_string()),
+                    emoji: None,
+                    disabled: false,
+                })],
+            })]),
+            ..Default::default()
+        }),
+    }))
+}
 
 async fn create_interaction(state: Arc<AppState>, interaction: Interaction) -> anyhow::Result<()> {
-    if interaction.kind == InteractionType::MessageComponent {
-        if let Some(InteractionData::MessageComponent(data)) = &interaction.data {
-            if data.custom_id.as_str() == "auth" {
-                let code = Uuid::new_v4();
-                {
-                    let mut conn = state.redis.get().await?;
-                    conn.set_ex::<_, _, ()>(
-                        format!("auth:{}", code),
-                        format!(
-                            "{}:{}",
-                            interaction.member.clone().unwrap().user.unwrap().id,
-                            interaction.guild_id.unwrap()
-                        ),
-                        60 * 5,
-                    )
-                    .await?;
-                };
-                let mut url = Url::parse(BASE_AUTH_URL.as_str())?;
-                url.query_pairs_mut().append_pair("code", &code.to_string());
-                state.interaction()
-                    .create_response(
-                        interaction.id,
-                        &interaction.token,
-                        &InteractionResponse {
-                            kind: InteractionResponseType::ChannelMessageWithSource,
-                            data: Some(InteractionResponseData {
-                                content: Some("認証を開始します。\n以下のボタンをクリックして飛んでください。".to_string()),
-                                flags: Some(MessageFlags::EPHEMERAL),
-                                components: Some(vec![
-                                    Component::ActionRow(ActionRow {
-                                        components: vec![Component::Button(Button {
-                                            style: ButtonStyle::Link,
-                                            label: Some("認証ページへ".to_string()),
-                                            custom_id: None,
-                                            url: Some(url.to_string()),
-                                            emoji: None,
-                                            disabled: false,
-                                        })],
-                                    }),
-                                ]),
-                                ..Default::default()
-                            }),
-                        },
-                    )
-                    .await?;
-            }
-        }
+    if let Some(response) = handle_interaction(&state, &interaction).await? {
+        state
+            .interaction()
+            .create_response(interaction.id, &interaction.token, &response)
+            .await?;
     }
     Ok(())
 }
 
+/// Drops the dashboard's Redis copies of the guild data changed by the event, so
+/// instances without a gateway cache refetch it on the next request.
+async fn invalidate_dashboard_cache(state: &AppState, event: &Event) -> anyhow::Result<()> {
+    let (guild_id, suffixes): (_, &[&str]) = match event {
+        Event::GuildUpdate(update) => (Some(update.0.id), &["", ":roles"]),
+        Event::RoleCreate(create) => (Some(create.guild_id), &[":roles"]),
+        Event::RoleUpdate(update) => (Some(update.guild_id), &[":roles"]),
+        Event::RoleDelete(delete) => (Some(delete.guild_id), &[":roles"]),
+        Event::ChannelCreate(channel) => (channel.0.guild_id, &[":channels"]),
+        Event::ChannelUpdate(channel) => (channel.0.guild_id, &[":channels"]),
+        Event::ChannelDelete(channel) => (channel.0.guild_id, &[":channels"]),
+        Event::ThreadCreate(thread) => (thread.0.guild_id, &[":channels"]),
+        Event::ThreadUpdate(thread) => (thread.0.guild_id, &[":channels"]),
+        Event::ThreadDelete(thread) => (Some(thread.guild_id), &[":channels"]),
+        Event::ThreadListSync(sync) => (Some(sync.guild_id), &[":channels"]),
+        _ => return Ok(()),
+    };
+    let Some(guild_id) = guild_id else {
+        return Ok(());
+    };
+    let keys = suffixes
+        .iter()
+        .map(|suffix| format!("dashboard:guild:{}{}", guild_id, suffix))
+        .collect::<Vec<_>>();
+    let mut conn = state.redis.get().await?;
+    conn.del::<_, ()>(keys).await?;
+    Ok(())
+}
+
+/// Runs the side effects of an event. The cache must already be updated.
 pub async fn receive_event(state: Arc<AppState>, event: Event) -> anyhow::Result<()> {
+    // A stale dashboard copy expires on its own, the handlers below must still run.
+    if let Err(error) = invalidate_dashboard_cache(&state, &event).await {
+        tracing::warn!("Failed to invalidate dashboard cache: {:?}", error);
+    }
     match event {
-        Event::InteractionCreate(interaction) => {
-            create_interaction(state, interaction.0).await?;
+        Event::InteractionCreate(interaction) => create_interaction(state, interaction.0).await?,
+        Event::GuildCreate(guild) => lifecycle::guild_created(&state, guild.0.id).await?,
+        Event::GuildDelete(delete) if !delete.unavailable => {
+            lifecycle::guild_deleted(&state, delete.id).await?
+        }
+        Event::RoleDelete(delete) => {
+            lifecycle::role_deleted(&state, delete.guild_id, delete.role_id).await?
+        }
+        Event::ChannelDelete(channel) => {
+            if let Some(guild_id) = channel.0.guild_id {
+                lifecycle::channel_deleted(&state, guild_id, channel.0.id).await?
+            }
+        }
+        Event::ThreadDelete(thread) => {
+            lifecycle::channel_deleted(&state, thread.guild_id, thread.id).await?
         }
         _ => {}
     }
     Ok(())
 }
 
-pub async fn run_bot(state: Arc<AppState>, token: String) -> anyhow::Result<()> {
-    let mut shard = Shard::new(ShardId::ONE, token, Intents::GUILDS);
+fn parse_shard_range(range: &str) -> Option<(u64, u64)> {
+    let (start, end) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
 
+/// Handles a shard's member joins and leaves one at a time, so a member leaving
+/// and quickly rejoining is processed in that order.
+async fn receive_member_events(state: Arc<AppState>, mut events: mpsc::UnboundedReceiver<Event>) {
+    while let Some(event) = events.recv().await {
+        let result = match event {
+            Event::MemberAdd(add) => {
+                lifecycle::member_added(
+                    &state,
+                    add.guild_id,
+                    add.member.user.id,
+                    add.member.joined_at,
+                )
+                .await
+            }
+            Event::MemberRemove(remove) => {
+                lifecycle::member_removed(&state, remove.guild_id, remove.user.id).await
+            }
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            tracing::error!("Failed to handle member event: {:?}", error);
+        }
+    }
+}
+
+async fn run_shard(state: Arc<AppState>, mut shard: Shard) {
+    let (member_events, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(receive_member_events(Arc::clone(&state), receiver));
     loop {
         let event = match shard.next_event().await {
             Ok(event) => event,
             Err(error) => {
-                tracing::warn!("Error receiving event: {:?}", error);
+                tracing::warn!("Error receiving event on shard {}: {:?}", shard.id(), error);
+                state.shards.update(&shard);
                 if error.is_fatal() {
                     break;
                 }
                 continue;
             }
         };
+        state.shards.update(&shard);
 
         tracing::debug!("Received event: {:?}", event);
 
-        tokio::spawn(receive_event(Arc::clone(&state), event));
+        // Applied in order here, only the side effects run concurrently.
+        state.cache.update(&event);
+        match event {
+            Event::MemberAdd(_) | Event::MemberRemove(_) => {
+                let _ = member_events.send(event);
+            }
+            event => {
+                tokio::spawn(receive_event(Arc::clone(&state), event));
+            }
+        }
     }
+}
 
-    Ok(())
+/// Creates the shards of this process. By default every shard of the recommended
+/// count is run; `SHARD_TOTAL` and `SHARD_RANGE` (inclusive, e.g. `0-3`) split
+/// the shards across several processes. Fails on an invalid shard config.
+pub async fn create_shards(state: &AppState, token: String) -> anyhow::Result<Vec<Shard>> {
+    let intents = if *MEMBER_EVENTS {
+        Intents::GUILDS | Intents::GUILD_MEMBERS
+    } else {
+        Intents::GUILDS
+    };
+    let config = Config::new(token, intents);
+    let shards = match (*SHARD_TOTAL, SHARD_RANGE.as_deref()) {
+        (Some(total), range) => {
+            let (start, end) = match range {
+                Some(range) => parse_shard_range(range)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid SHARD_RANGE: {}", range))?,
+                None => (0, total.saturating_sub(1)),
+            };
+            if start > end || end >= total {
+                anyhow::bail!("SHARD_RANGE must be within 0-{}", total.saturating_sub(1));
+            }
+            stream::create_range(start..=end, total, config, |_, builder| builder.build())
+                .collect::<Vec<_>>()
+        }
+        (None, Some(_)) => anyhow::bail!("SHARD_RANGE requires SHARD_TOTAL"),
+        (None, None) => {
+            stream::create_recommended(&state.http, config, |_, builder| builder.build())
+                .await?
+                .collect()
+        }
+    };
+    Ok(shards)
+}
+
+/// Runs the shards until every one of them has stopped.
+pub async fn run_bot(state: Arc<AppState>, shards: Vec<Shard>) {
+    tracing::info!("Start {} shards", shards.len());
+
+    let mut tasks = JoinSet::new();
+    for shard in shards {
+        state.shards.update(&shard);
+        tasks.spawn(run_shard(Arc::clone(&state), shard));
+    }
+    while tasks.join_next().await.is_some() {}
+    tracing::error!("Every shard has stopped");
 }
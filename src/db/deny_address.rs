@@ -0,0 +1,108 @@
+use crate::db::mail_address;
+use crate::utils::email::MailEntry;
+
+use sqlx::{PgPool, QueryBuilder};
+
+pub async fn add_deny_address(
+    pool: &PgPool,
+    guild_id: i64,
+    panel_id: i64,
+    email: String,
+    entry: &MailEntry,
+) -> anyhow::Result<i64> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO deny_address (guild_id, panel_id, email, kind, normalized_email, domain, local_pattern)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        guild_id,
+        panel_id as i32,
+        email,
+        entry.kind(),
+        entry.normalized_email(),
+        entry.domain(),
+        entry.local_pattern()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.id as i64)
+}
+
+pub async fn get_all_deny_address(
+    pool: &PgPool,
+    panel_id: i64,
+) -> anyhow::Result<Vec<(i64, String)>> {
+    let rows = sqlx::query!(
+        "SELECT id, email FROM deny_address WHERE panel_id = $1",
+        panel_id as i32
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id as i64, row.email))
+        .collect())
+}
+
+pub async fn delete_deny_address(pool: &PgPool, panel_id: i64, id: i64) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM deny_address WHERE panel_id = $1 AND id = $2",
+        panel_id as i32,
+        id as i32
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns the first denylist entry a normalized address hits, if any.
+pub async fn find_denied(
+    pool: &PgPool,
+    panel_id: i64,
+    normalized_email: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut query = QueryBuilder::new("SELECT email FROM deny_address WHERE panel_id = ");
+    query.push_bind(panel_id as i32).push(" AND ");
+    mail_address::push_entry_match(&mut query, normalized_email)?;
+    query.push(" LIMIT 1");
+    let email = query
+        .build_query_scalar::<String>()
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(email)
+}
+
+/// Members verified through the panel whose address the denylist entry now hits.
+pub async fn find_denied_members(
+    pool: &PgPool,
+    panel_id: i64,
+    deny_id: i64,
+) -> anyhow::Result<Vec<i64>> {
+    let user_ids = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT verification.user_id
+        FROM verification
+        JOIN deny_address ON deny_address.panel_id = verification.panel_id
+        WHERE deny_address.panel_id = $1 AND deny_address.id = $2
+            AND verification.left_at IS NULL
+            AND mail_entry_matches(
+                deny_address.kind,
+                deny_address.normalized_email,
+                deny_address.domain,
+                deny_address.local_pattern,
+                verification.normalized_email
+            )
+        "#,
+        panel_id as i32,
+        deny_id as i32
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(user_ids)
+}
@@ -0,0 +1,188 @@
+use sqlx::PgPool;
+
+/// How long a claimed job may run before another worker picks it up again.
+const LOCK_SECS: f64 = 5.0 * 60.0;
+
+pub struct ClaimedJob {
+    pub id: i64,
+    pub payload: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+pub struct DeadJob {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String,
+    pub idempotency_key: Option<String>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Enqueues a job. A job whose idempotency key was already used is dropped.
+/// Returns whether the job was enqueued.
+pub async fn add_job(
+    pool: &PgPool,
+    kind: &str,
+    payload: &str,
+    idempotency_key: Option<&str>,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO job (kind, payload, idempotency_key)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (idempotency_key) DO NOTHING
+        "#,
+        kind,
+        payload,
+        idempotency_key
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Claims the next due job, including jobs whose worker died while running them.
+pub async fn claim_job(pool: &PgPool) -> anyhow::Result<Option<ClaimedJob>> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE job
+        SET status = 'running', attempts = attempts + 1, updated_at = now(),
+            locked_until = now() + make_interval(secs => $1)
+        WHERE id = (
+            SELECT id FROM job
+            WHERE (status = 'pending' AND run_at <= now())
+                OR (status = 'running' AND locked_until < now())
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, payload, attempts, max_attempts
+        "#,
+        LOCK_SECS
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| ClaimedJob {
+        id: row.id,
+        payload: row.payload,
+        attempts: row.attempts,
+        max_attempts: row.max_attempts,
+    }))
+}
+
+pub async fn complete_job(pool: &PgPool, id: i64) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE job SET status = 'done', locked_until = NULL, last_error = NULL, updated_at = now()
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Schedules another attempt of a failed job after `delay_secs`.
+pub async fn retry_job_later(
+    pool: &PgPool,
+    id: i64,
+    error: &str,
+    delay_secs: f64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE job
+        SET status = 'pending', run_at = now() + make_interval(secs => $3), locked_until = NULL,
+            last_error = $2, updated_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        error,
+        delay_secs
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Gives up on a job, it stays in the dead-letter list until retried by an admin.
+pub async fn kill_job(pool: &PgPool, id: i64, error: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE job SET status = 'dead', locked_until = NULL, last_error = $2, updated_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        error
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_dead_jobs(pool: &PgPool) -> anyhow::Result<Vec<DeadJob>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, kind, payload, idempotency_key, attempts, last_error,
+            created_at::TEXT AS "created_at!", updated_at::TEXT AS "updated_at!"
+        FROM job
+        WHERE status = 'dead'
+        ORDER BY updated_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DeadJob {
+            id: row.id,
+            kind: row.kind,
+            payload: row.payload,
+            idempotency_key: row.idempotency_key,
+            attempts: row.attempts,
+            last_error: row.last_error,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+        .collect())
+}
+
+/// Requeues a dead job with a fresh set of attempts. Returns whether it was dead.
+pub async fn revive_job(pool: &PgPool, id: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE job SET status = 'pending', attempts = 0, run_at = now(), updated_at = now()
+        WHERE id = $1 AND status = 'dead'
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Deletes jobs that finished more than `retention_days` ago.
+pub async fn purge_done_jobs(pool: &PgPool, retention_days: i32) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM job
+        WHERE status = 'done' AND updated_at < now() - make_interval(days => $1)
+        "#,
+        retention_days
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
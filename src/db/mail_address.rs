@@ -1,15 +1,30 @@
-use sqlx::PgPool;
+use crate::utils::email::{self, MailEntry};
 
-pub async fn add_mail_address(pool: &PgPool, guild_id: i64, email: String) -> anyhow::Result<i64> {
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+pub async fn add_mail_address(
+    pool: &PgPool,
+    guild_id: i64,
+    panel_id: i64,
+    email: String,
+    entry: &MailEntry,
+    tags: Vec<String>,
+) -> anyhow::Result<i64> {
     // get id
     let row = sqlx::query!(
         r#"
-        INSERT INTO mail_address (guild_id, email)
-        VALUES ($1, $2)
+        INSERT INTO mail_address (guild_id, panel_id, email, kind, normalized_email, domain, local_pattern, tags)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         RETURNING id
         "#,
         guild_id,
-        email
+        panel_id as i32,
+        email,
+        entry.kind(),
+        entry.normalized_email(),
+        entry.domain(),
+        entry.local_pattern(),
+        &tags
     )
     .fetch_one(pool)
     .await?;
@@ -17,24 +32,27 @@ pub async fn add_mail_address(pool: &PgPool, guild_id: i64, email: String) -> an
     Ok(row.id as i64)
 }
 
-pub async fn get_all_email(pool: &PgPool, guild_id: i64) -> anyhow::Result<Vec<(i64, String)>> {
+pub async fn get_all_email(
+    pool: &PgPool,
+    panel_id: i64,
+) -> anyhow::Result<Vec<(i64, String, Vec<String>)>> {
     let rows = sqlx::query!(
-        "SELECT id, email FROM mail_address WHERE guild_id = $1",
-        guild_id
+        "SELECT id, email, tags FROM mail_address WHERE panel_id = $1",
+        panel_id as i32
     )
     .fetch_all(pool)
     .await?;
 
     Ok(rows
         .into_iter()
-        .map(|row| (row.id as i64, row.email))
+        .map(|row| (row.id as i64, row.email, row.tags))
         .collect())
 }
 
-pub async fn delete_mail_address(pool: &PgPool, guild_id: i64, id: i64) -> anyhow::Result<()> {
+pub async fn delete_mail_address(pool: &PgPool, panel_id: i64, id: i64) -> anyhow::Result<()> {
     sqlx::query!(
-        "DELETE FROM mail_address WHERE guild_id = $1 AND id = $2",
-        guild_id,
+        "DELETE FROM mail_address WHERE panel_id = $1 AND id = $2",
+        panel_id as i32,
         id as i32
     )
     .execute(pool)
@@ -43,18 +61,46 @@ pub async fn delete_mail_address(pool: &PgPool, guild_id: i64, id: i64) -> anyho
     Ok(())
 }
 
-pub async fn exist_mail(pool: &PgPool, guild_id: i64, email: String) -> anyhow::Result<bool> {
-    let row = sqlx::query!(
-        r#"
-        SELECT COUNT(*) as count
-        FROM mail_address
-        WHERE guild_id = $1 AND email = $2
-        "#,
-        guild_id,
-        email
-    )
-    .fetch_one(pool)
-    .await?;
+/// Appends the condition matching list entries (allow or deny) against a
+/// normalized address. Every branch compares `normalized_email` or `domain`
+/// directly, so lookups stay on the `(panel_id, ...)` indexes.
+pub fn push_entry_match(
+    query: &mut QueryBuilder<'_, Postgres>,
+    normalized_email: &str,
+) -> anyhow::Result<()> {
+    let (local, domain) = normalized_email
+        .rsplit_once('@')
+        .ok_or_else(|| anyhow::anyhow!("Invalid email address"))?;
+    query
+        .push("((kind = 'address' AND normalized_email = ")
+        .push_bind(normalized_email.to_string())
+        .push(") OR (kind = 'domain' AND domain = ")
+        .push_bind(domain.to_string())
+        .push(") OR (kind = 'subdomain' AND domain = ANY(")
+        .push_bind(email::parent_domains(domain))
+        .push(")) OR (kind = 'glob' AND domain = ")
+        .push_bind(domain.to_string())
+        .push(" AND ")
+        .push_bind(local.to_string())
+        .push(" LIKE local_pattern))");
+
+    Ok(())
+}
+
+/// Matches a normalized address against every entry kind of the panel's list.
+/// Returns the text and tags of each matching entry.
+pub async fn match_mail(
+    pool: &PgPool,
+    panel_id: i64,
+    normalized_email: &str,
+) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+    let mut query = QueryBuilder::new("SELECT email, tags FROM mail_address WHERE panel_id = ");
+    query.push_bind(panel_id as i32).push(" AND ");
+    push_entry_match(&mut query, normalized_email)?;
+    let rows = query
+        .build_query_as::<(String, Vec<String>)>()
+        .fetch_all(pool)
+        .await?;
 
-    Ok(row.count == Some(1))
+    Ok(rows)
 }
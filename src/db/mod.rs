@@ -1,3 +1,8 @@
+pub mod deny_address;
+pub mod job;
 pub mod mail_address;
+pub mod panel;
+pub mod tag_role;
 pub mod token;
+pub mod verification;
 pub mod verify;
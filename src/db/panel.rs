@@ -0,0 +1,397 @@
+use crate::utils::email::{MailEntry, NormalizeOptions};
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// The editable part of a verification panel.
+pub struct PanelSettings {
+    pub name: String,
+    pub email_pattern: String,
+    pub pattern_form: Option<String>,
+    pub role_id: i64,
+    pub channel_id: i64,
+    pub enable_check_mail: bool,
+    pub normalize: NormalizeOptions,
+    pub reject_disposable: bool,
+    pub reject_free_mail: bool,
+    pub content: String,
+}
+
+pub struct Panel {
+    pub id: i64,
+    pub guild_id: i64,
+    pub settings: PanelSettings,
+    /// Channel and message ID of the posted panel.
+    pub message: Option<(i64, i64)>,
+    /// Forum post created to hold the panel message, if the panel channel is a forum.
+    pub message_thread_id: Option<i64>,
+    pub settings_version: i64,
+    /// Why the panel can't verify anyone until its settings are saved again, e.g.
+    /// its role was deleted.
+    pub broken_reason: Option<String>,
+}
+
+struct PanelRow {
+    id: i32,
+    guild_id: i64,
+    name: String,
+    email_pattern: String,
+    pattern_form: Option<String>,
+    role_id: i64,
+    channel_id: i64,
+    enable_check_mail: bool,
+    lowercase_local_part: bool,
+    strip_plus_tag: bool,
+    ignore_gmail_dots: bool,
+    reject_disposable: bool,
+    reject_free_mail: bool,
+    content: String,
+    message_channel_id: Option<i64>,
+    message_id: Option<i64>,
+    message_thread_id: Option<i64>,
+    settings_version: i64,
+    broken_reason: Option<String>,
+}
+
+impl From<PanelRow> for Panel {
+    fn from(row: PanelRow) -> Self {
+        Self {
+            id: row.id as i64,
+            guild_id: row.guild_id,
+            settings: PanelSettings {
+                name: row.name,
+                email_pattern: row.email_pattern,
+                pattern_form: row.pattern_form,
+                role_id: row.role_id,
+                channel_id: row.channel_id,
+                enable_check_mail: row.enable_check_mail,
+                normalize: NormalizeOptions {
+                    lowercase_local_part: row.lowercase_local_part,
+                    strip_plus_tag: row.strip_plus_tag,
+                    ignore_gmail_dots: row.ignore_gmail_dots,
+                },
+                reject_disposable: row.reject_disposable,
+                reject_free_mail: row.reject_free_mail,
+                content: row.content,
+            },
+            message: row.message_channel_id.zip(row.message_id),
+            message_thread_id: row.message_thread_id,
+            settings_version: row.settings_version,
+            broken_reason: row.broken_reason,
+        }
+    }
+}
+
+pub async fn add_panel(
+    pool: &PgPool,
+    guild_id: i64,
+    settings: &PanelSettings,
+) -> anyhow::Result<i64> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO panel (guild_id, name, email_pattern, pattern_form, role_id, channel_id,
+            enable_check_mail, lowercase_local_part, strip_plus_tag, ignore_gmail_dots,
+            reject_disposable, reject_free_mail, content)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        RETURNING id
+        "#,
+        guild_id,
+        settings.name,
+        settings.email_pattern,
+        settings.pattern_form,
+        settings.role_id,
+        settings.channel_id,
+        settings.enable_check_mail,
+        settings.normalize.lowercase_local_part,
+        settings.normalize.strip_plus_tag,
+        settings.normalize.ignore_gmail_dots,
+        settings.reject_disposable,
+        settings.reject_free_mail,
+        settings.content
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.id as i64)
+}
+
+/// Re-parses the panel's allow and deny entries with `options`. Entries that no
+/// longer parse keep their previous form.
+async fn renormalize_entries(
+    tx: &mut Transaction<'_, Postgres>,
+    panel_id: i64,
+    options: &NormalizeOptions,
+) -> anyhow::Result<()> {
+    let mails = sqlx::query!(
+        "SELECT id, email FROM mail_address WHERE panel_id = $1 FOR UPDATE",
+        panel_id as i32
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    for row in mails {
+        let entry = match MailEntry::parse(&row.email, options) {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::warn!("Failed to normalize mail address {}: {}", row.id, error);
+                continue;
+            }
+        };
+        sqlx::query!(
+            r#"
+            UPDATE mail_address
+            SET kind = $2, normalized_email = $3, domain = $4, local_pattern = $5
+            WHERE id = $1
+            "#,
+            row.id,
+            entry.kind(),
+            entry.normalized_email(),
+            entry.domain(),
+            entry.local_pattern()
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    let denies = sqlx::query!(
+        "SELECT id, email FROM deny_address WHERE panel_id = $1 FOR UPDATE",
+        panel_id as i32
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    for row in denies {
+        let entry = match MailEntry::parse(&row.email, options) {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::warn!("Failed to normalize deny address {}: {}", row.id, error);
+                continue;
+            }
+        };
+        sqlx::query!(
+            r#"
+            UPDATE deny_address
+            SET kind = $2, normalized_email = $3, domain = $4, local_pattern = $5
+            WHERE id = $1
+            "#,
+            row.id,
+            entry.kind(),
+            entry.normalized_email(),
+            entry.domain(),
+            entry.local_pattern()
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Saves the settings and, if the normalization options changed, rewrites the
+/// stored form of the panel's allow and deny entries in the same transaction.
+pub async fn update_panel(
+    pool: &PgPool,
+    guild_id: i64,
+    id: i64,
+    settings: &PanelSettings,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    let old = sqlx::query!(
+        r#"
+        SELECT lowercase_local_part, strip_plus_tag, ignore_gmail_dots
+        FROM panel
+        WHERE guild_id = $1 AND id = $2
+        FOR UPDATE
+        "#,
+        guild_id,
+        id as i32
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|row| NormalizeOptions {
+        lowercase_local_part: row.lowercase_local_part,
+        strip_plus_tag: row.strip_plus_tag,
+        ignore_gmail_dots: row.ignore_gmail_dots,
+    });
+    sqlx::query!(
+        r#"
+        UPDATE panel
+        SET name = $3, email_pattern = $4, pattern_form = $5, role_id = $6, channel_id = $7,
+            enable_check_mail = $8, lowercase_local_part = $9, strip_plus_tag = $10,
+            ignore_gmail_dots = $11, reject_disposable = $12, reject_free_mail = $13,
+            content = $14, settings_version = settings_version + 1, broken_reason = NULL
+        WHERE guild_id = $1 AND id = $2
+        "#,
+        guild_id,
+        id as i32,
+        settings.name,
+        settings.email_pattern,
+        settings.pattern_form,
+        settings.role_id,
+        settings.channel_id,
+        settings.enable_check_mail,
+        settings.normalize.lowercase_local_part,
+        settings.normalize.strip_plus_tag,
+        settings.normalize.ignore_gmail_dots,
+        settings.reject_disposable,
+        settings.reject_free_mail,
+        settings.content
+    )
+    .execute(&mut *tx)
+    .await?;
+    if old.is_some_and(|old| old != settings.normalize) {
+        renormalize_entries(&mut tx, id, &settings.normalize).await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Normalizes allowlist addresses stored before normalization existed, which
+/// have an empty `normalized_email`. Returns the number of panels updated.
+pub async fn backfill_normalized_entries(pool: &PgPool) -> anyhow::Result<usize> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT panel.id, panel.lowercase_local_part, panel.strip_plus_tag,
+            panel.ignore_gmail_dots
+        FROM panel
+        JOIN mail_address ON mail_address.panel_id = panel.id
+        WHERE mail_address.kind = 'address' AND mail_address.normalized_email = ''
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in &rows {
+        let options = NormalizeOptions {
+            lowercase_local_part: row.lowercase_local_part,
+            strip_plus_tag: row.strip_plus_tag,
+            ignore_gmail_dots: row.ignore_gmail_dots,
+        };
+        let mut tx = pool.begin().await?;
+        renormalize_entries(&mut tx, row.id as i64, &options).await?;
+        tx.commit().await?;
+    }
+
+    Ok(rows.len())
+}
+
+pub async fn delete_panel(pool: &PgPool, guild_id: i64, id: i64) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM panel WHERE guild_id = $1 AND id = $2",
+        guild_id,
+        id as i32
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_panels(pool: &PgPool, guild_id: i64) -> anyhow::Result<Vec<Panel>> {
+    let rows = sqlx::query_as!(
+        PanelRow,
+        "SELECT * FROM panel WHERE guild_id = $1 ORDER BY id",
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Panel::from).collect())
+}
+
+pub async fn get_panel(pool: &PgPool, guild_id: i64, id: i64) -> anyhow::Result<Option<Panel>> {
+    let row = sqlx::query_as!(
+        PanelRow,
+        "SELECT * FROM panel WHERE guild_id = $1 AND id = $2",
+        guild_id,
+        id as i32
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Panel::from))
+}
+
+/// The guild's oldest panel, used by the single-panel settings routes and by
+/// buttons posted before panels had their own ID.
+pub async fn get_default_panel(pool: &PgPool, guild_id: i64) -> anyhow::Result<Option<Panel>> {
+    let row = sqlx::query_as!(
+        PanelRow,
+        "SELECT * FROM panel WHERE guild_id = $1 ORDER BY id LIMIT 1",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(Panel::from))
+}
+
+pub async fn set_panel_message(
+    pool: &PgPool,
+    id: i64,
+    channel_id: i64,
+    message_id: i64,
+    thread_id: Option<i64>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE panel SET message_channel_id = $2, message_id = $3, message_thread_id = $4
+        WHERE id = $1
+        "#,
+        id as i32,
+        channel_id,
+        message_id,
+        thread_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks the panels granting a deleted role as broken. Returns the names of the
+/// affected panels.
+pub async fn mark_broken_by_role(
+    pool: &PgPool,
+    guild_id: i64,
+    role_id: i64,
+    reason: &str,
+) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query!(
+        r#"
+        UPDATE panel SET broken_reason = $3
+        WHERE guild_id = $1 AND role_id = $2
+        RETURNING name
+        "#,
+        guild_id,
+        role_id,
+        reason
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.name).collect())
+}
+
+/// Marks the panels posted in a deleted channel as broken and forgets their
+/// message, which went with the channel. Returns the names of the affected panels.
+pub async fn mark_broken_by_channel(
+    pool: &PgPool,
+    guild_id: i64,
+    channel_id: i64,
+    reason: &str,
+) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query!(
+        r#"
+        UPDATE panel
+        SET broken_reason = $3, message_channel_id = NULL, message_id = NULL,
+            message_thread_id = NULL
+        WHERE guild_id = $1 AND channel_id = $2
+        RETURNING name
+        "#,
+        guild_id,
+        channel_id,
+        reason
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.name).collect())
+}
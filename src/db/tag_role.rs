@@ -0,0 +1,75 @@
+use sqlx::PgPool;
+
+pub async fn set_tag_roles(
+    pool: &PgPool,
+    guild_id: i64,
+    panel_id: i64,
+    tag_roles: Vec<(String, i64)>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM tag_role WHERE panel_id = $1", panel_id as i32)
+        .execute(&mut *tx)
+        .await?;
+    for (tag, role_id) in tag_roles {
+        sqlx::query!(
+            r#"
+            INSERT INTO tag_role (guild_id, panel_id, tag, role_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+            "#,
+            guild_id,
+            panel_id as i32,
+            tag,
+            role_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn get_tag_roles(pool: &PgPool, panel_id: i64) -> anyhow::Result<Vec<(String, i64)>> {
+    let rows = sqlx::query!(
+        "SELECT tag, role_id FROM tag_role WHERE panel_id = $1 ORDER BY tag",
+        panel_id as i32
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.tag, row.role_id)).collect())
+}
+
+pub async fn get_roles_by_tags(
+    pool: &PgPool,
+    panel_id: i64,
+    tags: &[String],
+) -> anyhow::Result<Vec<i64>> {
+    let rows = sqlx::query!(
+        "SELECT DISTINCT role_id FROM tag_role WHERE panel_id = $1 AND tag = ANY($2)",
+        panel_id as i32,
+        tags
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.role_id).collect())
+}
+
+/// Removes a deleted role from every tag. Returns the tags it was granted for.
+pub async fn delete_role(
+    pool: &PgPool,
+    guild_id: i64,
+    role_id: i64,
+) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query!(
+        "DELETE FROM tag_role WHERE guild_id = $1 AND role_id = $2 RETURNING tag",
+        guild_id,
+        role_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.tag).collect())
+}
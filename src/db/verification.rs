@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// What happens to a member's verifications when they leave the guild.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberLeaveAction {
+    /// Keep the verification bound to the member.
+    #[default]
+    Keep,
+    /// Keep the record but release the address for other accounts.
+    Release,
+    /// Delete the record.
+    Delete,
+}
+
+impl MemberLeaveAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Release => "release",
+            Self::Delete => "delete",
+        }
+    }
+
+    pub fn parse(action: &str) -> Self {
+        match action {
+            "release" => Self::Release,
+            "delete" => Self::Delete,
+            _ => Self::Keep,
+        }
+    }
+}
+
+/// A member's successful verification through a panel.
+pub struct Verification {
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub panel_id: i64,
+    /// The address as the member entered it.
+    pub email: String,
+    /// The address under the panel's normalization, bound to the member.
+    pub normalized_email: String,
+    /// Roles granted on verification.
+    pub role_ids: Vec<i64>,
+}
+
+/// The member's verifications whose address is still bound to them.
+pub async fn get_member_verifications(
+    pool: &PgPool,
+    guild_id: i64,
+    user_id: i64,
+) -> anyhow::Result<Vec<Verification>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT guild_id, user_id, panel_id, email, normalized_email, role_ids
+        FROM verification
+        WHERE guild_id = $1 AND user_id = $2 AND NOT released
+        "#,
+        guild_id,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Verification {
+            guild_id: row.guild_id,
+            user_id: row.user_id,
+            panel_id: row.panel_id as i64,
+            email: row.email,
+            normalized_email: row.normalized_email,
+            role_ids: row.role_ids,
+        })
+        .collect())
+}
+
+/// Verifications of the members still in the guild.
+pub async fn get_guild_verifications(
+    pool: &PgPool,
+    guild_id: i64,
+) -> anyhow::Result<Vec<Verification>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT guild_id, user_id, panel_id, email, normalized_email, role_ids
+        FROM verification
+        WHERE guild_id = $1 AND left_at IS NULL
+        ORDER BY user_id, panel_id
+        "#,
+        guild_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Verification {
+            guild_id: row.guild_id,
+            user_id: row.user_id,
+            panel_id: row.panel_id as i64,
+            email: row.email,
+            normalized_email: row.normalized_email,
+            role_ids: row.role_ids,
+        })
+        .collect())
+}
+
+pub async fn set_verification_roles(
+    pool: &PgPool,
+    guild_id: i64,
+    user_id: i64,
+    panel_id: i64,
+    role_ids: &[i64],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE verification SET role_ids = $4
+        WHERE guild_id = $1 AND user_id = $2 AND panel_id = $3
+        "#,
+        guild_id,
+        user_id,
+        panel_id as i32,
+        role_ids
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the verification. With `one_per_account`, nothing is recorded if
+/// another member's verification in the guild holds the address; that member's
+/// ID is returned instead.
+pub async fn record_verification(
+    pool: &PgPool,
+    verification: &Verification,
+    one_per_account: bool,
+) -> anyhow::Result<Option<i64>> {
+    let mut tx = pool.begin().await?;
+    if one_per_account {
+        // The row to conflict with may not exist yet, so claims of an address are
+        // serialized with a lock instead.
+        sqlx::query!(
+            "SELECT 1 AS locked FROM pg_advisory_xact_lock(hashtext($1::BIGINT::TEXT || ':' || $2))",
+            verification.guild_id,
+            verification.normalized_email
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let owner = sqlx::query_scalar!(
+            r#"
+            SELECT user_id
+            FROM verification
+            WHERE guild_id = $1 AND normalized_email = $2 AND user_id <> $3 AND NOT released
+            LIMIT 1
+            "#,
+            verification.guild_id,
+            verification.normalized_email,
+            verification.user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if owner.is_some() {
+            return Ok(owner);
+        }
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO verification (guild_id, user_id, panel_id, email, normalized_email, role_ids)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (guild_id, user_id, panel_id) DO UPDATE
+        SET email = $4, normalized_email = $5, role_ids = $6, verified_at = now(),
+            left_at = NULL, released = false
+        "#,
+        verification.guild_id,
+        verification.user_id,
+        verification.panel_id as i32,
+        verification.email,
+        verification.normalized_email,
+        &verification.role_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(None)
+}
+
+/// Marks the member's verifications as left, releasing their addresses if `release`.
+pub async fn mark_member_left(
+    pool: &PgPool,
+    guild_id: i64,
+    user_id: i64,
+    release: bool,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE verification SET left_at = now(), released = released OR $3
+        WHERE guild_id = $1 AND user_id = $2
+        "#,
+        guild_id,
+        user_id,
+        release
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_member_verifications(
+    pool: &PgPool,
+    guild_id: i64,
+    user_id: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM verification WHERE guild_id = $1 AND user_id = $2",
+        guild_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_member_leave_action(
+    pool: &PgPool,
+    guild_id: i64,
+    action: MemberLeaveAction,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET member_leave_action = $2 WHERE guild_id = $1",
+        guild_id,
+        action.as_str()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_member_leave_action(
+    pool: &PgPool,
+    guild_id: i64,
+) -> anyhow::Result<MemberLeaveAction> {
+    let row = sqlx::query!(
+        "SELECT member_leave_action FROM email_verify WHERE guild_id = $1",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .map(|row| MemberLeaveAction::parse(&row.member_leave_action))
+        .unwrap_or_default())
+}
+
+pub async fn set_reapply_on_rejoin(
+    pool: &PgPool,
+    guild_id: i64,
+    reapply_on_rejoin: bool,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET reapply_on_rejoin = $2 WHERE guild_id = $1",
+        guild_id,
+        reapply_on_rejoin
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether members who rejoin get the roles of their stored verifications back.
+pub async fn get_reapply_on_rejoin(pool: &PgPool, guild_id: i64) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT reapply_on_rejoin FROM email_verify WHERE guild_id = $1",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.is_some_and(|row| row.reapply_on_rejoin))
+}
+
+pub async fn set_one_address_per_account(
+    pool: &PgPool,
+    guild_id: i64,
+    one_address_per_account: bool,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET one_address_per_account = $2 WHERE guild_id = $1",
+        guild_id,
+        one_address_per_account
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether an address can only be verified by one member of the guild at a time.
+/// Addresses of members who left stay bound unless the leave action releases them.
+pub async fn get_one_address_per_account(pool: &PgPool, guild_id: i64) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT one_address_per_account FROM email_verify WHERE guild_id = $1",
+        guild_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.is_some_and(|row| row.one_address_per_account))
+}
@@ -1,25 +1,14 @@
 use sqlx::PgPool;
 
-pub async fn add_guild(
-    pool: &PgPool,
-    guild_id: i64,
-    email_pattern: String,
-    role_id: i64,
-    channel_id: i64,
-    enable_check_mail: bool,
-) -> anyhow::Result<()> {
+/// Creates the guild's settings row if it doesn't exist yet.
+pub async fn add_guild(pool: &PgPool, guild_id: i64) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
-        INSERT INTO email_verify (guild_id, email_pattern, role_id, channel_id, enable_check_mail)
-        VALUES ($1, $2, $3, $4, $5)
-        ON CONFLICT (guild_id)
-        DO UPDATE SET email_pattern = $2, role_id = $3, channel_id = $4, enable_check_mail = $5
+        INSERT INTO email_verify (guild_id)
+        VALUES ($1)
+        ON CONFLICT (guild_id) DO NOTHING
         "#,
-        guild_id,
-        email_pattern,
-        role_id,
-        channel_id,
-        enable_check_mail
+        guild_id
     )
     .execute(pool)
     .await?;
@@ -27,23 +16,110 @@ pub async fn add_guild(
     Ok(())
 }
 
-pub async fn get_guild(
+pub async fn set_log_channel(
     pool: &PgPool,
     guild_id: i64,
-) -> anyhow::Result<Option<(String, i64, i64, bool)>> {
+    channel_id: Option<i64>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET log_channel_id = $2 WHERE guild_id = $1",
+        guild_id,
+        channel_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Channel where admins are notified about problems, the owner's DMs are used if unset.
+pub async fn get_log_channel(pool: &PgPool, guild_id: i64) -> anyhow::Result<Option<i64>> {
     let row = sqlx::query!(
-        "SELECT email_pattern, role_id, channel_id, enable_check_mail FROM email_verify WHERE guild_id = $1",
+        "SELECT log_channel_id FROM email_verify WHERE guild_id = $1",
         guild_id
     )
     .fetch_optional(pool)
     .await?;
 
-    Ok(row.map(|row| {
-        (
-            row.email_pattern,
-            row.role_id,
-            row.channel_id,
-            row.enable_check_mail,
-        )
-    }))
+    Ok(row.and_then(|row| row.log_channel_id))
+}
+
+/// Unsets the log channel if it is the deleted channel.
+pub async fn clear_log_channel(
+    pool: &PgPool,
+    guild_id: i64,
+    channel_id: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET log_channel_id = NULL WHERE guild_id = $1 AND log_channel_id = $2",
+        guild_id,
+        channel_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a guild the bot was removed from. Its data is purged after the retention window.
+pub async fn mark_guild_left(pool: &PgPool, guild_id: i64) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_verify SET left_at = now() WHERE guild_id = $1 AND left_at IS NULL",
+        guild_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Restores a guild the bot was re-added to. Returns whether it had been marked as left.
+pub async fn mark_guild_joined(pool: &PgPool, guild_id: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE email_verify SET left_at = NULL WHERE guild_id = $1 AND left_at IS NOT NULL",
+        guild_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks every guild as left or joined depending on whether it is in `guild_ids`,
+/// the guilds the bot is in. Returns the number of guilds marked as left.
+pub async fn reconcile_guilds(pool: &PgPool, guild_ids: &[i64]) -> anyhow::Result<u64> {
+    sqlx::query!(
+        "UPDATE email_verify SET left_at = NULL WHERE guild_id = ANY($1) AND left_at IS NOT NULL",
+        guild_ids
+    )
+    .execute(pool)
+    .await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE email_verify SET left_at = now()
+        WHERE NOT (guild_id = ANY($1)) AND left_at IS NULL
+        "#,
+        guild_ids
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Deletes the data of guilds the bot left more than `retention_days` ago.
+/// Returns the purged guild IDs.
+pub async fn purge_left_guilds(pool: &PgPool, retention_days: i32) -> anyhow::Result<Vec<i64>> {
+    let rows = sqlx::query!(
+        r#"
+        DELETE FROM email_verify
+        WHERE left_at < now() - make_interval(days => $1)
+        RETURNING guild_id
+        "#,
+        retention_days
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.guild_id).collect())
 }
@@ -23,7 +23,26 @@ async fn main() -> anyhow::Result<()> {
         .await?,
     );
 
-    tokio::spawn(bot::run_bot(Arc::clone(&state), token));
+    let backfilled = db::panel::backfill_normalized_entries(&state.pool).await?;
+    if backfilled > 0 {
+        tracing::info!("Normalized the allowlists of {} panels", backfilled);
+    }
+
+    let workers = env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|workers| workers.trim().parse().ok())
+        .unwrap_or(2);
+    for _ in 0..workers {
+        tokio::spawn(utils::jobs::run_worker(Arc::clone(&state)));
+    }
+    tokio::spawn(utils::jobs::run_purge(Arc::clone(&state)));
+    tokio::spawn(utils::retention::run_retention(Arc::clone(&state)));
+
+    // Web-only instances receive interactions over HTTP and leave the gateway to others.
+    if !env::var("DISABLE_GATEWAY").is_ok_and(|disable| disable == "true") {
+        let shards = bot::create_shards(&state, token).await?;
+        tokio::spawn(bot::run_bot(Arc::clone(&state), shards));
+    }
 
     server::run_server(Arc::clone(&state)).await?;
     Ok(())
@@ -1,10 +1,13 @@
+use crate::utils::rate_limit::{EXCHANGE_TOKEN_PER_IP, VERIFY_PER_IP};
 use crate::utils::state::AppState;
 
 use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::{
     http::{HeaderValue, Method},
+    middleware,
     routing::{delete, get, post, put},
     Router,
 };
@@ -12,6 +15,7 @@ use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+mod rate_limit;
 mod result;
 mod routes;
 mod token;
@@ -20,10 +24,23 @@ pub async fn run_server(state: Arc<AppState>) -> anyhow::Result<()> {
     let allow_origin = env::var("BASE_URL")?;
     let app = Router::new()
         .route("/auth", get(routes::auth::main_path))
-        .route("/auth/verify/discord", post(routes::auth::verify_discord))
+        .route(
+            "/auth/verify/discord",
+            post(routes::auth::verify_discord).layer(middleware::from_fn_with_state(
+                (Arc::clone(&state), &VERIFY_PER_IP),
+                rate_limit::limit_by_ip,
+            )),
+        )
+        .route(
+            "/interactions",
+            post(routes::interactions::receive_interaction),
+        )
         .route(
             "/dashboard/exchange_token",
-            post(routes::dashboard::callback),
+            post(routes::dashboard::callback).layer(middleware::from_fn_with_state(
+                (Arc::clone(&state), &EXCHANGE_TOKEN_PER_IP),
+                rate_limit::limit_by_ip,
+            )),
         )
         .route("/dashboard/users/@me", get(routes::dashboard::get_me))
         .route(
@@ -50,6 +67,34 @@ pub async fn run_server(state: Arc<AppState>) -> anyhow::Result<()> {
             "/dashboard/guilds/:guild_id/general_settings",
             get(routes::dashboard::get_guild_general_settings),
         )
+        .route(
+            "/dashboard/guilds/:guild_id/panels",
+            get(routes::dashboard::get_panels),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels",
+            post(routes::dashboard::add_panel),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id",
+            put(routes::dashboard::update_panel),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id",
+            delete(routes::dashboard::delete_panel),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/repost",
+            post(routes::dashboard::repost_panel),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panel/repost",
+            post(routes::dashboard::repost_default_panel),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/pattern/test",
+            post(routes::dashboard::test_pattern),
+        )
         .route(
             "/dashboard/guilds/:guild_id/mails",
             get(routes::dashboard::get_all_mail_addresses),
@@ -58,10 +103,92 @@ pub async fn run_server(state: Arc<AppState>) -> anyhow::Result<()> {
             "/dashboard/guilds/:guild_id/mails",
             post(routes::dashboard::add_mail_address),
         )
+        .route(
+            "/dashboard/guilds/:guild_id/mails/import",
+            post(routes::dashboard::import_mail_addresses),
+        )
         .route(
             "/dashboard/guilds/:guild_id/mails/:mail_id",
             delete(routes::dashboard::delete_mail_address),
         )
+        .route(
+            "/dashboard/guilds/:guild_id/denylist",
+            get(routes::dashboard::get_all_deny_addresses),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/denylist",
+            post(routes::dashboard::add_deny_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/denylist/:deny_id",
+            delete(routes::dashboard::delete_deny_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/tag_roles",
+            get(routes::dashboard::get_tag_roles),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/tag_roles",
+            put(routes::dashboard::set_tag_roles),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/mails",
+            get(routes::dashboard::get_all_mail_addresses),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/mails",
+            post(routes::dashboard::add_mail_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/mails/import",
+            post(routes::dashboard::import_mail_addresses),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/mails/:mail_id",
+            delete(routes::dashboard::delete_mail_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/denylist",
+            get(routes::dashboard::get_all_deny_addresses),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/denylist",
+            post(routes::dashboard::add_deny_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/denylist/:deny_id",
+            delete(routes::dashboard::delete_deny_address),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/tag_roles",
+            get(routes::dashboard::get_tag_roles),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/panels/:panel_id/tag_roles",
+            put(routes::dashboard::set_tag_roles),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/diagnostics",
+            get(routes::dashboard::get_diagnostics),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/reconcile",
+            get(routes::dashboard::get_reconcile_progress),
+        )
+        .route(
+            "/dashboard/guilds/:guild_id/reconcile",
+            post(routes::dashboard::reconcile_roles),
+        )
+        .route(
+            "/admin/disposable_domains/reload",
+            post(routes::admin::reload_disposable_domains),
+        )
+        .route("/admin/shards", get(routes::admin::get_shards))
+        .route("/admin/jobs/dead", get(routes::admin::get_dead_jobs))
+        .route(
+            "/admin/jobs/:job_id/retry",
+            post(routes::admin::retry_dead_job),
+        )
         .route("/invite_url", get(routes::invite_url))
         .layer(
             CorsLayer::new()
@@ -73,7 +200,11 @@ pub async fn run_server(state: Arc<AppState>) -> anyhow::Result<()> {
         .with_state(Arc::clone(&state));
 
     let listener = TcpListener::bind("0.0.0.0:8000").await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,130 @@
+use super::result::APIError;
+use crate::utils::rate_limit::{self, Limit};
+use crate::utils::state::AppState;
+
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use once_cell::sync::Lazy;
+
+/// Header set by the reverse proxy with the client IP, e.g. `X-Forwarded-For`.
+/// Without it the peer address is used.
+static CLIENT_IP_HEADER: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("CLIENT_IP_HEADER")
+        .ok()
+        .filter(|header| !header.is_empty())
+});
+/// Proxies in front of the server that append to `CLIENT_IP_HEADER`. Entries
+/// left of theirs are sent by the client and can't be trusted.
+static TRUSTED_PROXY_HOPS: Lazy<usize> = Lazy::new(|| {
+    env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|hops| hops.trim().parse().ok())
+        .filter(|hops| *hops > 0)
+        .unwrap_or(1)
+});
+
+/// The address the outermost trusted proxy saw, `hops` entries from the right of
+/// the header. Falls back to the peer address if the header is missing or shorter.
+fn client_ip(headers: &HeaderMap, addr: SocketAddr, header: Option<&str>, hops: usize) -> String {
+    header
+        .and_then(|header| headers.get(header)?.to_str().ok())
+        .and_then(|value| {
+            let entries = value.split(',').map(str::trim).collect::<Vec<_>>();
+            entries.len().checked_sub(hops).map(|index| entries[index])
+        })
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| addr.ip().to_string())
+}
+
+/// Middleware limiting a route per client IP.
+pub async fn limit_by_ip(
+    State((state, limit)): State<(Arc<AppState>, &'static Limit)>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = client_ip(
+        request.headers(),
+        addr,
+        CLIENT_IP_HEADER.as_deref(),
+        *TRUSTED_PROXY_HOPS,
+    );
+    match rate_limit::hit(&state, limit, &ip).await {
+        Ok(None) => next.run(request).await,
+        Ok(Some(retry_after)) => APIError::too_many_requests(retry_after).into_response(),
+        Err(error) => APIError::from(error).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:1234".parse().unwrap()
+    }
+
+    #[test]
+    fn uses_peer_without_header_config() {
+        let headers = headers("203.0.113.7");
+        assert_eq!(client_ip(&headers, peer(), None, 1), "10.0.0.1");
+    }
+
+    #[test]
+    fn uses_peer_without_header() {
+        let headers = HeaderMap::new();
+        assert_eq!(
+            client_ip(&headers, peer(), Some("x-forwarded-for"), 1),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn takes_rightmost_entry() {
+        let headers = headers("1.1.1.1, 203.0.113.7");
+        assert_eq!(
+            client_ip(&headers, peer(), Some("x-forwarded-for"), 1),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn skips_trusted_hops() {
+        let headers = headers("1.1.1.1, 203.0.113.7, 198.51.100.2");
+        assert_eq!(
+            client_ip(&headers, peer(), Some("x-forwarded-for"), 2),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn uses_peer_when_header_is_shorter_than_hops() {
+        let headers = headers("203.0.113.7");
+        assert_eq!(
+            client_ip(&headers, peer(), Some("x-forwarded-for"), 2),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn uses_peer_for_empty_entry() {
+        let headers = headers("203.0.113.7, ");
+        assert_eq!(
+            client_ip(&headers, peer(), Some("x-forwarded-for"), 1),
+            "10.0.0.1"
+        );
+    }
+}
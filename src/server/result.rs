@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -9,6 +9,9 @@ use serde::{Deserialize, Serialize};
 pub struct ResponseAPIError {
     pub status: u16,
     pub message: String,
+    /// Text meant to be shown to the member as is, e.g. a guild's custom failure text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_message: Option<String>,
 }
 
 pub type APIResult<T> = Result<T, APIError>;
@@ -16,6 +19,9 @@ pub type APIResult<T> = Result<T, APIError>;
 pub struct APIError {
     pub status: StatusCode,
     pub message: String,
+    pub display_message: Option<String>,
+    /// Seconds to wait before retrying, sent as `Retry-After`.
+    pub retry_after: Option<u64>,
 }
 
 impl IntoResponse for APIError {
@@ -23,8 +29,15 @@ impl IntoResponse for APIError {
         let response = Json(ResponseAPIError {
             status: self.status.as_u16(),
             message: self.message,
+            display_message: self.display_message,
         });
-        (self.status, response).into_response()
+        let mut response = (self.status, response).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
     }
 }
 
@@ -36,6 +49,8 @@ where
         Self {
             status: StatusCode::INTERNAL_SERVER_ERROR,
             message: err.into().to_string(),
+            display_message: None,
+            retry_after: None,
         }
     }
 }
@@ -45,6 +60,8 @@ impl APIError {
         Self {
             status: StatusCode::NOT_FOUND,
             message: message.to_string(),
+            display_message: None,
+            retry_after: None,
         }
     }
 
@@ -52,6 +69,8 @@ impl APIError {
         Self {
             status: StatusCode::FORBIDDEN,
             message: message.to_string(),
+            display_message: None,
+            retry_after: None,
         }
     }
 
@@ -59,6 +78,8 @@ impl APIError {
         Self {
             status: StatusCode::UNAUTHORIZED,
             message: message.to_string(),
+            display_message: None,
+            retry_after: None,
         }
     }
 
@@ -66,6 +87,40 @@ impl APIError {
         Self {
             status: StatusCode::BAD_REQUEST,
             message: message.to_string(),
+            display_message: None,
+            retry_after: None,
         }
     }
+
+    pub fn conflict(message: &str) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            message: message.to_string(),
+            display_message: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn unavailable(message: &str) -> Self {
+        Self {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: message.to_string(),
+            display_message: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn too_many_requests(retry_after: u64) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: "Too many requests".to_string(),
+            display_message: None,
+            retry_after: Some(retry_after),
+        }
+    }
+
+    pub fn with_display_message(mut self, display_message: String) -> Self {
+        self.display_message = Some(display_message);
+        self
+    }
 }
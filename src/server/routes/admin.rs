@@ -0,0 +1,108 @@
+use crate::db::job as job_db;
+use crate::server::result::{APIError, APIResult};
+use crate::server::token::Token;
+use crate::utils::provider::DISPOSABLE_DOMAINS_PATH;
+use crate::utils::shard::ShardStatus;
+use crate::utils::state::AppState;
+
+use std::env;
+use std::sync::Arc;
+
+use axum::extract::{Json, Path, State};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+static ADMIN_USER_IDS: Lazy<Vec<u64>> = Lazy::new(|| {
+    env::var("ADMIN_USER_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+});
+
+fn admin_checker(token: &Token) -> APIResult<()> {
+    if !ADMIN_USER_IDS.contains(&token.user_id) {
+        return Err(APIError::forbitten("You are not an administrator"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ResponseReloadDisposableDomains {
+    count: usize,
+}
+
+pub async fn reload_disposable_domains(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+) -> APIResult<Json<ResponseReloadDisposableDomains>> {
+    admin_checker(&token)?;
+
+    let path = DISPOSABLE_DOMAINS_PATH
+        .as_deref()
+        .ok_or_else(|| APIError::badrequest("DISPOSABLE_DOMAINS_PATH is not set"))?;
+    let count = state.disposable_domains.reload()?;
+    tracing::info!("Reload {} disposable domains from {}", count, path);
+
+    Ok(Json(ResponseReloadDisposableDomains { count }))
+}
+
+pub async fn get_shards(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+) -> APIResult<Json<Vec<ShardStatus>>> {
+    admin_checker(&token)?;
+
+    Ok(Json(state.shards.all()))
+}
+
+#[derive(Serialize)]
+pub struct ResponseDeadJob {
+    id: i64,
+    kind: String,
+    payload: serde_json::Value,
+    idempotency_key: Option<String>,
+    attempts: i32,
+    last_error: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+/// Jobs that failed permanently or ran out of attempts.
+pub async fn get_dead_jobs(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+) -> APIResult<Json<Vec<ResponseDeadJob>>> {
+    admin_checker(&token)?;
+
+    let jobs = job_db::get_dead_jobs(&state.pool)
+        .await?
+        .into_iter()
+        .map(|job| ResponseDeadJob {
+            id: job.id,
+            kind: job.kind,
+            payload: serde_json::from_str(&job.payload).unwrap_or_default(),
+            idempotency_key: job.idempotency_key,
+            attempts: job.attempts,
+            last_error: job.last_error,
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+        })
+        .collect();
+
+    Ok(Json(jobs))
+}
+
+pub async fn retry_dead_job(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(job_id): Path<i64>,
+) -> APIResult<()> {
+    admin_checker(&token)?;
+
+    if !job_db::revive_job(&state.pool, job_id).await? {
+        return Err(APIError::notfound("Not found"));
+    }
+
+    Ok(())
+}
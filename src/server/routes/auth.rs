@@ -1,23 +1,23 @@
-use crate::db::mail_address as mail_db;
-use crate::db::verify as db;
+use crate::db::panel as panel_db;
+use crate::db::verification::{self as verification_db, Verification};
 use crate::server::result::{APIError, APIResult};
+use crate::utils::auth_state::{self, AuthState, Claim};
+use crate::utils::panel::PanelContent;
+use crate::utils::rate_limit::{self, VERIFY_PER_GUILD, VERIFY_PER_USER};
+use crate::utils::roles;
+use crate::utils::rules::{Rules, Verdict};
 use crate::utils::state::AppState;
 
 use std::env;
 use std::sync::Arc;
 
 use axum::extract::{Json, State};
-use bb8_redis::redis::AsyncCommands;
 use once_cell::sync::Lazy;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
-use twilight_http::request::AuditLogReason;
 use twilight_http::Client as HttpClient;
-use twilight_model::id::Id;
 use twilight_model::user::CurrentUser;
 
 static DISCORD_CLIENT_ID: Lazy<String> = Lazy::new(|| env::var("DISCORD_CLIENT_ID").unwrap());
-static BASE_URL: Lazy<String> = Lazy::new(|| env::var("BASE_URL").unwrap());
 static DISCORD_CLIENT_SECRET: Lazy<String> =
     Lazy::new(|| env::var("DISCORD_CLIENT_SECRET").unwrap());
 
@@ -35,8 +35,11 @@ pub struct RequestVerifyDiscord {
 pub struct ResponseVerifyDiscord {
     status: i32,
     user: CurrentUser,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct DiscordTokenResponse {
     access_token: String,
@@ -50,19 +53,52 @@ pub async fn verify_discord(
     State(state): State<Arc<AppState>>,
     Json(query): Json<RequestVerifyDiscord>,
 ) -> APIResult<Json<ResponseVerifyDiscord>> {
+    // Rate limited before the claim, so a refused request doesn't use up an attempt.
+    let Some(auth) = auth_state::peek(&state, &query.state).await? else {
+        return Err(APIError::badrequest("Invalid state"));
+    };
+    let limits = [
+        (&VERIFY_PER_USER, auth.user_id.to_string()),
+        (&VERIFY_PER_GUILD, auth.guild_id.to_string()),
+    ];
+    if let Some(retry_after) = rate_limit::hit_all(&state, &limits).await? {
+        return Err(APIError::too_many_requests(retry_after));
+    }
+    let auth = match auth_state::claim(&state, &query.state).await? {
+        Claim::Claimed(auth) => auth,
+        Claim::NotFound => return Err(APIError::badrequest("Invalid state")),
+        Claim::InUse => return Err(APIError::conflict("State is already being verified")),
+        Claim::Locked => return Err(APIError::forbitten("Too many attempts")),
+    };
+    let result = verify(&state, &query, auth).await;
+    if result.is_ok() {
+        auth_state::consume(&state, &query.state).await?;
+    } else {
+        auth_state::release(&state, &query.state).await?;
+    }
+    result
+}
+
+async fn verify(
+    state: &AppState,
+    query: &RequestVerifyDiscord,
+    auth: AuthState,
+) -> APIResult<Json<ResponseVerifyDiscord>> {
+    let (guild_id, user_id) = (auth.guild_id, auth.user_id as i64);
+    let Some(panel) = panel_db::get_panel(&state.pool, guild_id, auth.panel_id).await? else {
+        return Err(APIError::notfound("Panel not found"));
+    };
     let client = reqwest::Client::new();
     let response: DiscordTokenResponse = client
         .post("https://discord.com/api/v10/oauth2/token")
         .header("Content-Type", "application/x-www-form-urlencoded")
         .form(&[
-            ("client_id", DISCORD_CLIENT_ID.clone()),
-            ("client_secret", DISCORD_CLIENT_SECRET.clone()),
-            ("grant_type", "authorization_code".to_string()),
-            ("code", query.code),
-            (
-                "redirect_uri",
-                format!("{}/auth/callback/discord", *BASE_URL),
-            ),
+            ("client_id", DISCORD_CLIENT_ID.as_str()),
+            ("client_secret", DISCORD_CLIENT_SECRET.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code", &query.code),
+            ("redirect_uri", &auth_state::REDIRECT_URI),
+            ("code_verifier", &auth.code_verifier),
         ])
         .send()
         .await?
@@ -72,54 +108,67 @@ pub async fn verify_discord(
 
     let http = HttpClient::new(format!("Bearer {}", response.access_token));
     let user = http.current_user().await?.model().await?;
-    let (user_id, guild_id) = {
-        let mut conn = state.redis.get().await?;
-        let data: String = conn.get(&format!("auth:{}", query.state)).await?;
-        if let Some((user_id, guild_id)) = data.split_once(':') {
-            (user_id.to_string(), guild_id.to_string())
-        } else {
-            return Err(APIError::badrequest("Invalid state"));
-        }
-    };
-    let user_id = user_id.parse::<u64>().unwrap();
-    if user.id.get() != user_id {
+    if user.id.get() != auth.user_id {
         return Err(APIError::badrequest("Invalid user"));
     }
-    let guild_id = guild_id.parse::<i64>().unwrap();
-    if let Some((email_pattern, role_id, _, enable_check_mail)) =
-        db::get_guild(&state.pool, guild_id).await?
-    {
-        if user.email.is_none() {
-            return Err(APIError::badrequest("Email not found"));
-        }
-        let pattern = Regex::new(&email_pattern)?;
-        if !pattern.is_match(user.email.as_ref().unwrap()) {
-            return Err(APIError::badrequest("Mail is not match"));
-        }
-        if enable_check_mail
-            && !mail_db::exist_mail(
-                &state.pool,
-                guild_id,
-                user.email.clone().unwrap_or("".to_string()),
-            )
-            .await?
-        {
-            return Err(APIError::badrequest("Mail is not inside at list"));
-        }
-        state
-            .http
-            .add_guild_member_role(
-                Id::new(guild_id as u64),
-                Id::new(user_id),
-                Id::new(role_id as u64),
-            )
-            .reason("Verified email")?
-            .await?;
-        {
-            let mut conn = state.redis.get().await?;
-            conn.del(&format!("auth:{}", query.state)).await?;
+    let rules = Rules::load(state, &panel).await?;
+    let Some(email) = user.email.as_ref() else {
+        return Err(APIError::badrequest("Email not found"));
+    };
+    let texts = PanelContent::from_panel(&panel)?.texts(user.locale.as_deref().unwrap_or_default());
+    if let Some(reason) = &panel.broken_reason {
+        return Err(
+            APIError::unavailable(&format!("Guild is misconfigured: {}", reason))
+                .with_display_message(texts.failure_message),
+        );
+    }
+    let (normalized_email, tags) = match rules.evaluate(state, email).await? {
+        Verdict::Accepted {
+            normalized_email,
+            tags,
+            ..
+        } => (normalized_email, tags),
+        verdict => {
+            let error = if matches!(verdict, Verdict::Denied { .. }) {
+                APIError::forbitten(verdict.reason())
+            } else {
+                APIError::badrequest(verdict.reason())
+            };
+            return Err(error.with_display_message(texts.failure_message));
         }
+    };
+    let verification = Verification {
+        guild_id,
+        user_id,
+        panel_id: panel.id,
+        email: email.clone(),
+        normalized_email,
+        role_ids: roles::verified_role_ids(state, &panel, &tags).await?,
+    };
+    let one_per_account =
+        verification_db::get_one_address_per_account(&state.pool, guild_id).await?;
+    if verification_db::record_verification(&state.pool, &verification, one_per_account)
+        .await?
+        .is_some()
+    {
+        return Err(
+            APIError::forbitten("Mail is already used by another account")
+                .with_display_message(texts.failure_message),
+        );
     }
+    roles::grant_roles(
+        state,
+        guild_id,
+        user_id,
+        &verification.role_ids,
+        "Verified email",
+        &format!("verify:{}", query.state),
+    )
+    .await?;
 
-    Ok(Json(ResponseVerifyDiscord { status: 200, user }))
+    Ok(Json(ResponseVerifyDiscord {
+        status: 200,
+        user,
+        message: Some(texts.success_message),
+    }))
 }
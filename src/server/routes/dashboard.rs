@@ -1,8 +1,21 @@
+use crate::bot;
+use crate::db::deny_address as deny_db;
 use crate::db::mail_address as mail_db;
+use crate::db::panel::{self as panel_db, Panel, PanelSettings};
+use crate::db::tag_role as tag_db;
 use crate::db::token as db;
+use crate::db::verification::{self as verification_db, MemberLeaveAction};
 use crate::db::verify as verify_db;
 use crate::server::result::{APIError, APIResult};
 use crate::server::token::Token;
+use crate::utils::email::{MailEntry, NormalizeOptions};
+use crate::utils::lock;
+use crate::utils::panel::{self, PanelContent};
+use crate::utils::pattern::{self, PatternForm};
+use crate::utils::permission::{self, BotPermissions, Problem};
+use crate::utils::rate_limit::{self, EXCHANGE_TOKEN_PER_USER};
+use crate::utils::reconcile::{self, Progress};
+use crate::utils::rules::{Rules, Verdict};
 use crate::utils::state::AppState;
 
 use std::env;
@@ -13,16 +26,13 @@ use base64::prelude::*;
 use bb8_redis::redis::AsyncCommands;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use sparkle_interactions::builder::component::{ButtonBuilder, ComponentsBuilder};
 use twilight_http::Client as HttpClient;
-use twilight_model::channel::message::component::ButtonStyle;
-use twilight_model::channel::{Channel, ChannelType};
+use twilight_model::channel::Channel;
 use twilight_model::guild::Guild;
 use twilight_model::guild::Permissions;
 use twilight_model::guild::Role;
 use twilight_model::id::Id;
 use twilight_model::user::{CurrentUser, CurrentUserGuild};
-use twilight_util::builder::embed::EmbedBuilder;
 use twilight_util::permission_calculator::PermissionCalculator;
 
 static DISCORD_CLIENT_ID: Lazy<String> = Lazy::new(|| env::var("DISCORD_CLIENT_ID").unwrap());
@@ -30,6 +40,7 @@ static BASE_URL: Lazy<String> = Lazy::new(|| env::var("BASE_URL").unwrap());
 static DISCORD_CLIENT_SECRET: Lazy<String> =
     Lazy::new(|| env::var("DISCORD_CLIENT_SECRET").unwrap());
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct RequestDashboardCallback {
     code: String,
@@ -42,6 +53,7 @@ pub struct ResponseDashboardCallback {
     token: String,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct DiscordTokenResponse {
     access_token: String,
@@ -73,6 +85,11 @@ pub async fn callback(
 
     let http = HttpClient::new(format!("Bearer {}", response.access_token));
     let user = http.current_user().await?.model().await?;
+    if let Some(retry_after) =
+        rate_limit::hit(&state, &EXCHANGE_TOKEN_PER_USER, &user.id.to_string()).await?
+    {
+        return Err(APIError::too_many_requests(retry_after));
+    }
 
     let token = Token::new(user.id.get())?;
     let nonce = BASE64_URL_SAFE_NO_PAD.encode(token.nonce);
@@ -107,7 +124,7 @@ pub async fn get_me(
     let user = {
         let mut conn = state.redis.get().await?;
         let data: Option<String> = conn
-            .get(&format!("dashboard:user:{}", token.user_id))
+            .get(format!("dashboard:user:{}", token.user_id))
             .await?;
         if let Some(data) = data {
             serde_json::from_str(&data)?
@@ -135,7 +152,7 @@ pub async fn get_me_guilds(
     let guilds = {
         let mut conn = state.redis.get().await?;
         let data: Option<String> = conn
-            .get(&format!("dashboard:user:guild:{}", token.user_id))
+            .get(format!("dashboard:user:guild:{}", token.user_id))
             .await?;
         if let Some(data) = data {
             serde_json::from_str(&data)?
@@ -161,24 +178,41 @@ async fn permission_checker(
     guild_id: u64,
     user_id: u64,
 ) -> anyhow::Result<bool> {
-    let member = state
-        .http
-        .guild_member(Id::new(guild_id), Id::new(user_id))
-        .await?
-        .model()
-        .await?;
-    let guild_roles = state.http.roles(Id::new(guild_id)).await?.model().await?;
+    let (guild_id, user_id) = (Id::new(guild_id), Id::new(user_id));
+    // Without member events a cached entry would never see a demotion or a
+    // kick, so the roles are fetched fresh on every call.
+    let cached = match *bot::MEMBER_EVENTS {
+        true => state.cache.member_roles(guild_id, user_id),
+        false => None,
+    };
+    let member_roles = match cached {
+        Some(roles) => roles,
+        None => {
+            let member = state
+                .http
+                .guild_member(guild_id, user_id)
+                .await?
+                .model()
+                .await?;
+            if *bot::MEMBER_EVENTS {
+                state
+                    .cache
+                    .insert_member(guild_id, user_id, member.roles.clone());
+            }
+            member.roles
+        }
+    };
+    let guild_roles = match state.cache.roles(guild_id) {
+        Some(roles) => roles,
+        None => state.http.roles(guild_id).await?.model().await?,
+    };
     let member_roles = guild_roles
         .iter()
-        .filter(|role| member.roles.contains(&role.id))
+        .filter(|role| member_roles.contains(&role.id))
         .map(|role| (role.id, role.permissions))
         .collect::<Vec<_>>();
-    let calculator = PermissionCalculator::new(
-        Id::new(guild_id),
-        Id::new(user_id),
-        Permissions::empty(),
-        &member_roles,
-    );
+    let calculator =
+        PermissionCalculator::new(guild_id, user_id, Permissions::empty(), &member_roles);
     if calculator.root().contains(Permissions::ADMINISTRATOR) {
         return Ok(true);
     }
@@ -192,7 +226,7 @@ pub async fn get_guild(
 ) -> APIResult<Json<Guild>> {
     let guild = {
         let mut conn = state.redis.get().await?;
-        let data: Option<String> = conn.get(&format!("dashboard:guild:{}", guild_id)).await?;
+        let data: Option<String> = conn.get(format!("dashboard:guild:{}", guild_id)).await?;
         if let Some(data) = data {
             serde_json::from_str(&data)?
         } else {
@@ -226,10 +260,12 @@ pub async fn get_guild_roles(
             "You don't have permission to access this guild",
         ));
     }
-    let roles = {
+    let roles = if let Some(roles) = state.cache.roles(Id::new(guild_id)) {
+        roles
+    } else {
         let mut conn = state.redis.get().await?;
         let data: Option<String> = conn
-            .get(&format!("dashboard:guild:{}:roles", guild_id))
+            .get(format!("dashboard:guild:{}:roles", guild_id))
             .await?;
         if let Some(data) = data {
             serde_json::from_str(&data)?
@@ -263,20 +299,29 @@ pub async fn get_guild_text_channels(
             "You don't have permission to access this guild",
         ));
     }
-    let channels = {
+    let channels = if let Some(channels) = state.cache.channels(Id::new(guild_id)) {
+        channels
+    } else {
         let mut conn = state.redis.get().await?;
         let data: Option<String> = conn
-            .get(&format!("dashboard:guild:{}:channels", guild_id))
+            .get(format!("dashboard:guild:{}:channels", guild_id))
             .await?;
         if let Some(data) = data {
             serde_json::from_str(&data)?
         } else {
-            let channels = state
+            let mut channels = state
                 .http
                 .guild_channels(Id::new(guild_id))
                 .await?
                 .model()
                 .await?;
+            let threads = state
+                .http
+                .active_threads(Id::new(guild_id))
+                .await?
+                .model()
+                .await?;
+            channels.extend(threads.threads);
             conn.set_ex::<_, _, ()>(
                 format!("dashboard:guild:{}:channels", guild_id),
                 serde_json::to_string(&channels)?,
@@ -288,19 +333,139 @@ pub async fn get_guild_text_channels(
     };
     let channels = channels
         .iter()
-        .filter(|channel| channel.kind == ChannelType::GuildText)
+        .filter(|channel| permission::is_panel_channel(channel.kind))
         .cloned()
         .collect();
 
     Ok(Json(channels))
 }
 
+/// Parses a Discord ID sent as a string. Discord IDs are never zero.
+fn parse_id(value: &str, name: &str) -> APIResult<i64> {
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|id| *id > 0)
+        .ok_or_else(|| APIError::badrequest(&format!("Invalid {}", name)))
+}
+
+/// Settings of a single verification panel as edited in the dashboard.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct GuildGeneralSettings {
+pub struct PanelSettingsBody {
+    #[serde(default)]
+    name: String,
+    /// Ignored on save when `pattern_form` is set, the form's regex is stored instead.
+    #[serde(default)]
     email_pattern: String,
+    #[serde(default)]
+    pattern_form: Option<PatternForm>,
     role_id: String,
     channel_id: String,
     enable_check_mail: bool,
+    #[serde(default)]
+    normalize: NormalizeOptions,
+    #[serde(default)]
+    reject_disposable: bool,
+    #[serde(default)]
+    reject_free_mail: bool,
+    #[serde(default)]
+    panel: PanelContent,
+}
+
+impl PanelSettingsBody {
+    fn into_settings(self) -> APIResult<PanelSettings> {
+        let role_id = parse_id(&self.role_id, "role_id")?;
+        let channel_id = parse_id(&self.channel_id, "channel_id")?;
+        let email_pattern = match &self.pattern_form {
+            Some(form) => form.to_regex(),
+            None => pattern::compile(&self.email_pattern).map(|_| self.email_pattern.clone()),
+        }
+        .map_err(|error| APIError::badrequest(&format!("Invalid pattern: {}", error)))?;
+        self.panel
+            .validate()
+            .map_err(|error| APIError::badrequest(&format!("Invalid panel: {}", error)))?;
+
+        Ok(PanelSettings {
+            name: self.name,
+            email_pattern,
+            pattern_form: self
+                .pattern_form
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+            role_id,
+            channel_id,
+            enable_check_mail: self.enable_check_mail,
+            normalize: self.normalize,
+            reject_disposable: self.reject_disposable,
+            reject_free_mail: self.reject_free_mail,
+            content: serde_json::to_string(&self.panel)?,
+        })
+    }
+
+    fn from_panel(panel: &Panel) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: panel.settings.name.clone(),
+            email_pattern: panel.settings.email_pattern.clone(),
+            pattern_form: panel
+                .settings
+                .pattern_form
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            role_id: panel.settings.role_id.to_string(),
+            channel_id: panel.settings.channel_id.to_string(),
+            enable_check_mail: panel.settings.enable_check_mail,
+            normalize: panel.settings.normalize,
+            reject_disposable: panel.settings.reject_disposable,
+            reject_free_mail: panel.settings.reject_free_mail,
+            panel: PanelContent::from_panel(panel)?,
+        })
+    }
+}
+
+/// Rejects panel settings with a role the bot can't grant or a channel it can't post in.
+async fn check_panel_settings(
+    state: &AppState,
+    guild_id: u64,
+    settings: &PanelSettings,
+) -> APIResult<()> {
+    let permissions = BotPermissions::load(state, guild_id).await?;
+    let problem = match permissions.check_role(settings.role_id) {
+        Some(problem) => Some(problem),
+        None => {
+            permissions
+                .check_panel_channel(state, settings.channel_id)
+                .await?
+        }
+    };
+    if let Some(problem) = problem {
+        return Err(APIError::badrequest(&problem.message()));
+    }
+    Ok(())
+}
+
+/// Guild-wide options together with the guild's default panel.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GuildGeneralSettings {
+    #[serde(flatten)]
+    settings: PanelSettingsBody,
+    #[serde(default)]
+    member_leave_action: MemberLeaveAction,
+    #[serde(default)]
+    reapply_on_rejoin: bool,
+    /// Refuses an address already bound to another member of the guild.
+    #[serde(default)]
+    one_address_per_account: bool,
+    /// Whether this bot receives member events, without them the two options above
+    /// can't be changed.
+    #[serde(skip_deserializing)]
+    member_events: bool,
+    /// Channel for notifications about broken settings, the owner is DMed if unset.
+    #[serde(default)]
+    log_channel_id: Option<String>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    broken_reason: Option<String>,
 }
 
 pub async fn set_guild_general_settings(
@@ -309,43 +474,84 @@ pub async fn set_guild_general_settings(
     Path(guild_id): Path<u64>,
     Json(body): Json<GuildGeneralSettings>,
 ) -> APIResult<()> {
-    let role_id = body.role_id.parse::<i64>()?;
-    let channel_id = body.channel_id.parse::<u64>()?;
+    let settings = body.settings.into_settings()?;
+    let log_channel_id = body
+        .log_channel_id
+        .as_deref()
+        .filter(|channel_id| !channel_id.is_empty())
+        .map(|channel_id| parse_id(channel_id, "log_channel_id"))
+        .transpose()?;
+    if !*bot::MEMBER_EVENTS
+        && (body.member_leave_action != MemberLeaveAction::Keep || body.reapply_on_rejoin)
+    {
+        return Err(APIError::badrequest(
+            "Member leave actions and rejoin reapply require member events",
+        ));
+    }
 
     if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
         return Err(APIError::forbitten(
             "You don't have permission to access this guild",
         ));
     }
+    check_panel_settings(&state, guild_id, &settings).await?;
+    if let Some(log_channel_id) = log_channel_id {
+        let permissions = BotPermissions::load(&state, guild_id).await?;
+        if let Some(problem) = permissions
+            .check_log_channel(&state, log_channel_id)
+            .await?
+        {
+            return Err(APIError::badrequest(&problem.message()));
+        }
+    }
 
-    verify_db::add_guild(
+    verify_db::add_guild(&state.pool, guild_id as i64).await?;
+    verify_db::set_log_channel(&state.pool, guild_id as i64, log_channel_id).await?;
+    verification_db::set_member_leave_action(
         &state.pool,
         guild_id as i64,
-        body.email_pattern.clone(),
-        role_id,
-        channel_id as i64,
-        body.enable_check_mail,
+        body.member_leave_action,
     )
     .await?;
-
-    let embed = EmbedBuilder::new()
-        .title("認証パネル")
-        .description("ボタンをクリックすると認証が始まります。")
-        .build();
-    let components = ComponentsBuilder::new()
-        .buttons(vec![ButtonBuilder::with_custom_id(
-            "auth".to_string(),
-            "認証する".to_string(),
-            ButtonStyle::Success,
-        )
-        .build()])
-        .build();
-    state
-        .http
-        .create_message(Id::new(channel_id))
-        .embeds(&[embed])?
-        .components(&components)?
+    verification_db::set_reapply_on_rejoin(&state.pool, guild_id as i64, body.reapply_on_rejoin)
         .await?;
+    verification_db::set_one_address_per_account(
+        &state.pool,
+        guild_id as i64,
+        body.one_address_per_account,
+    )
+    .await?;
+
+    let panel_id = match panel_db::get_default_panel(&state.pool, guild_id as i64).await? {
+        Some(panel) => {
+            panel_db::update_panel(&state.pool, guild_id as i64, panel.id, &settings).await?;
+            panel.id
+        }
+        None => panel_db::add_panel(&state.pool, guild_id as i64, &settings).await?,
+    };
+    let panel = panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::publish(&state, &panel, false).await?;
+
+    Ok(())
+}
+
+pub async fn repost_default_panel(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+) -> APIResult<()> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = panel_db::get_default_panel(&state.pool, guild_id as i64)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::publish(&state, &panel, true).await?;
 
     Ok(())
 }
@@ -361,22 +567,251 @@ pub async fn get_guild_general_settings(
         ));
     }
 
-    let (email_pattern, role_id, channel_id, enable_check_mail) =
-        verify_db::get_guild(&state.pool, guild_id as i64)
-            .await?
-            .ok_or_else(|| APIError::notfound("Not found"))?;
+    let panel = panel_db::get_default_panel(&state.pool, guild_id as i64)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    let member_leave_action =
+        verification_db::get_member_leave_action(&state.pool, guild_id as i64).await?;
+    let reapply_on_rejoin =
+        verification_db::get_reapply_on_rejoin(&state.pool, guild_id as i64).await?;
+    let one_address_per_account =
+        verification_db::get_one_address_per_account(&state.pool, guild_id as i64).await?;
+    let log_channel_id = verify_db::get_log_channel(&state.pool, guild_id as i64).await?;
 
     Ok(Json(GuildGeneralSettings {
-        email_pattern,
-        role_id: role_id.to_string(),
-        channel_id: channel_id.to_string(),
-        enable_check_mail,
+        settings: PanelSettingsBody::from_panel(&panel)?,
+        member_leave_action,
+        reapply_on_rejoin,
+        one_address_per_account,
+        member_events: *bot::MEMBER_EVENTS,
+        log_channel_id: log_channel_id.map(|channel_id| channel_id.to_string()),
+        broken_reason: panel.broken_reason,
     }))
 }
 
+#[derive(Serialize)]
+pub struct ResponsePanel {
+    id: i64,
+    #[serde(flatten)]
+    settings: PanelSettingsBody,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broken_reason: Option<String>,
+}
+
+pub async fn get_panels(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+) -> APIResult<Json<Vec<ResponsePanel>>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let mut panels = Vec::new();
+    for panel in panel_db::get_panels(&state.pool, guild_id as i64).await? {
+        panels.push(ResponsePanel {
+            id: panel.id,
+            settings: PanelSettingsBody::from_panel(&panel)?,
+            broken_reason: panel.broken_reason,
+        });
+    }
+
+    Ok(Json(panels))
+}
+
+#[derive(Serialize)]
+pub struct ResponseAddPanel {
+    id: i64,
+}
+
+pub async fn add_panel(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+    Json(body): Json<PanelSettingsBody>,
+) -> APIResult<Json<ResponseAddPanel>> {
+    let settings = body.into_settings()?;
+
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+    check_panel_settings(&state, guild_id, &settings).await?;
+
+    verify_db::add_guild(&state.pool, guild_id as i64).await?;
+    let panel_id = panel_db::add_panel(&state.pool, guild_id as i64, &settings).await?;
+    let panel = panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::publish(&state, &panel, false).await?;
+
+    Ok(Json(ResponseAddPanel { id: panel_id }))
+}
+
+pub async fn update_panel(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path((guild_id, panel_id)): Path<(u64, i64)>,
+    Json(body): Json<PanelSettingsBody>,
+) -> APIResult<()> {
+    let settings = body.into_settings()?;
+
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+    check_panel_settings(&state, guild_id, &settings).await?;
+
+    if panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .is_none()
+    {
+        return Err(APIError::notfound("Not found"));
+    }
+    panel_db::update_panel(&state.pool, guild_id as i64, panel_id, &settings).await?;
+    let panel = panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::publish(&state, &panel, false).await?;
+
+    Ok(())
+}
+
+pub async fn delete_panel(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path((guild_id, panel_id)): Path<(u64, i64)>,
+) -> APIResult<()> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::unpublish(&state, &panel).await;
+    panel_db::delete_panel(&state.pool, guild_id as i64, panel_id).await?;
+
+    Ok(())
+}
+
+pub async fn repost_panel(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path((guild_id, panel_id)): Path<(u64, i64)>,
+) -> APIResult<()> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = panel_db::get_panel(&state.pool, guild_id as i64, panel_id)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+    panel::publish(&state, &panel, true).await?;
+
+    Ok(())
+}
+
+/// The panel a request refers to, the guild's default panel when no ID is given.
+async fn resolve_panel(state: &AppState, guild_id: u64, panel_id: Option<i64>) -> APIResult<Panel> {
+    match panel_id {
+        Some(panel_id) => panel_db::get_panel(&state.pool, guild_id as i64, panel_id).await?,
+        None => panel_db::get_default_panel(&state.pool, guild_id as i64).await?,
+    }
+    .ok_or_else(|| APIError::notfound("Not found"))
+}
+
+/// Path of a list route: `/dashboard/guilds/:guild_id/...` for the default
+/// panel, or `/dashboard/guilds/:guild_id/panels/:panel_id/...`.
+#[derive(Deserialize)]
+pub struct ListPath {
+    guild_id: u64,
+    panel_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct MailPath {
+    guild_id: u64,
+    panel_id: Option<i64>,
+    mail_id: i64,
+}
+
+#[derive(Deserialize)]
+pub struct DenyPath {
+    guild_id: u64,
+    panel_id: Option<i64>,
+    deny_id: i64,
+}
+
+const MAX_PATTERN_TEST_MAILS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct RequestTestPattern {
+    mails: Vec<String>,
+    /// Panel whose rules are tested, the guild's default panel when unset.
+    #[serde(default)]
+    panel_id: Option<i64>,
+    /// Tests an unsaved pattern instead of the stored one.
+    #[serde(default)]
+    email_pattern: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseTestPattern {
+    mail: String,
+    matched: bool,
+    #[serde(flatten)]
+    verdict: Verdict,
+}
+
+pub async fn test_pattern(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+    Json(body): Json<RequestTestPattern>,
+) -> APIResult<Json<Vec<ResponseTestPattern>>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+    if body.mails.len() > MAX_PATTERN_TEST_MAILS {
+        return Err(APIError::badrequest("Too many mails"));
+    }
+
+    let panel = resolve_panel(&state, guild_id, body.panel_id).await?;
+    let mut rules = Rules::load(&state, &panel).await?;
+    if let Some(email_pattern) = body.email_pattern {
+        rules.pattern = pattern::compile(&email_pattern)
+            .map_err(|error| APIError::badrequest(&format!("Invalid pattern: {}", error)))?;
+    }
+
+    let mut results = Vec::new();
+    for mail in body.mails {
+        let verdict = rules.evaluate(&state, &mail).await?;
+        results.push(ResponseTestPattern {
+            mail,
+            matched: matches!(verdict, Verdict::Accepted { .. }),
+            verdict,
+        });
+    }
+
+    Ok(Json(results))
+}
+
 #[derive(Deserialize)]
 pub struct RequestAddMailAddress {
     mail: String,
+    #[serde(default)]
+    tags: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -387,7 +822,7 @@ pub struct ResponseAddMailAddress {
 pub async fn add_mail_address(
     State(state): State<Arc<AppState>>,
     token: Token,
-    Path(guild_id): Path<u64>,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
     Json(body): Json<RequestAddMailAddress>,
 ) -> APIResult<Json<ResponseAddMailAddress>> {
     if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
@@ -396,23 +831,75 @@ pub async fn add_mail_address(
         ));
     }
 
-    let mail = body.mail.clone();
-    let mail_id = mail_db::add_mail_address(&state.pool, guild_id as i64, mail).await?;
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let entry = MailEntry::parse(&body.mail, &panel.settings.normalize)
+        .map_err(|_| APIError::badrequest("Invalid email"))?;
+    let mail_id = mail_db::add_mail_address(
+        &state.pool,
+        guild_id as i64,
+        panel.id,
+        body.mail.clone(),
+        &entry,
+        body.tags,
+    )
+    .await?;
 
     Ok(Json(ResponseAddMailAddress { id: mail_id }))
 }
 
+#[derive(Serialize)]
+pub struct ResponseImportMailAddresses {
+    ids: Vec<i64>,
+    invalid: Vec<String>,
+}
+
+pub async fn import_mail_addresses(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
+    Json(body): Json<Vec<RequestAddMailAddress>>,
+) -> APIResult<Json<ResponseImportMailAddresses>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let mut ids = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in body {
+        let Ok(parsed) = MailEntry::parse(&entry.mail, &panel.settings.normalize) else {
+            invalid.push(entry.mail);
+            continue;
+        };
+        let mail_id = mail_db::add_mail_address(
+            &state.pool,
+            guild_id as i64,
+            panel.id,
+            entry.mail,
+            &parsed,
+            entry.tags,
+        )
+        .await?;
+        ids.push(mail_id);
+    }
+
+    Ok(Json(ResponseImportMailAddresses { ids, invalid }))
+}
+
 #[derive(Serialize)]
 pub struct ResponseGetAllMailAddress {
     mail: String,
     id: i64,
     guild_id: String,
+    tags: Vec<String>,
 }
 
 pub async fn get_all_mail_addresses(
     State(state): State<Arc<AppState>>,
     token: Token,
-    Path(guild_id): Path<u64>,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
 ) -> APIResult<Json<Vec<ResponseGetAllMailAddress>>> {
     if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
         return Err(APIError::forbitten(
@@ -420,15 +907,17 @@ pub async fn get_all_mail_addresses(
         ));
     }
 
-    let mails = mail_db::get_all_email(&state.pool, guild_id as i64).await?;
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let mails = mail_db::get_all_email(&state.pool, panel.id).await?;
 
     Ok(Json(
         mails
             .iter()
-            .map(|(id, mail)| ResponseGetAllMailAddress {
+            .map(|(id, mail, tags)| ResponseGetAllMailAddress {
                 id: *id,
                 mail: mail.clone(),
                 guild_id: guild_id.to_string(),
+                tags: tags.clone(),
             })
             .collect(),
     ))
@@ -437,7 +926,176 @@ pub async fn get_all_mail_addresses(
 pub async fn delete_mail_address(
     State(state): State<Arc<AppState>>,
     token: Token,
-    Path((guild_id, mail_id)): Path<(u64, i64)>,
+    Path(MailPath {
+        guild_id,
+        panel_id,
+        mail_id,
+    }): Path<MailPath>,
+) -> APIResult<()> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    mail_db::delete_mail_address(&state.pool, panel.id, mail_id).await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagRoles {
+    tag: String,
+    role_ids: Vec<String>,
+}
+
+pub async fn get_tag_roles(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
+) -> APIResult<Json<Vec<TagRoles>>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let mut tag_roles: Vec<TagRoles> = Vec::new();
+    for (tag, role_id) in tag_db::get_tag_roles(&state.pool, panel.id).await? {
+        match tag_roles.last_mut() {
+            Some(last) if last.tag == tag => last.role_ids.push(role_id.to_string()),
+            _ => tag_roles.push(TagRoles {
+                tag,
+                role_ids: vec![role_id.to_string()],
+            }),
+        }
+    }
+
+    Ok(Json(tag_roles))
+}
+
+pub async fn set_tag_roles(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
+    Json(body): Json<Vec<TagRoles>>,
+) -> APIResult<()> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+
+    let permissions = BotPermissions::load(&state, guild_id).await?;
+    let mut tag_roles = Vec::new();
+    for entry in body {
+        for role_id in entry.role_ids {
+            let role_id = role_id
+                .parse::<i64>()
+                .map_err(|_| APIError::badrequest("Invalid role_id"))?;
+            if let Some(problem) = permissions.check_role(role_id) {
+                return Err(APIError::badrequest(&problem.message()));
+            }
+            tag_roles.push((entry.tag.clone(), role_id));
+        }
+    }
+    tag_db::set_tag_roles(&state.pool, guild_id as i64, panel.id, tag_roles).await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct RequestAddDenyAddress {
+    mail: String,
+}
+
+#[derive(Serialize)]
+pub struct ResponseAddDenyAddress {
+    id: i64,
+    /// Verified members the new entry hits. A reconciliation removes their roles.
+    affected_user_ids: Vec<String>,
+}
+
+pub async fn add_deny_address(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
+    Json(body): Json<RequestAddDenyAddress>,
+) -> APIResult<Json<ResponseAddDenyAddress>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let entry = MailEntry::parse(&body.mail, &panel.settings.normalize)
+        .map_err(|_| APIError::badrequest("Invalid email"))?;
+    let deny_id = deny_db::add_deny_address(
+        &state.pool,
+        guild_id as i64,
+        panel.id,
+        body.mail.clone(),
+        &entry,
+    )
+    .await?;
+
+    let affected_user_ids = deny_db::find_denied_members(&state.pool, panel.id, deny_id)
+        .await?
+        .into_iter()
+        .map(|user_id| user_id.to_string())
+        .collect();
+
+    Ok(Json(ResponseAddDenyAddress {
+        id: deny_id,
+        affected_user_ids,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ResponseGetAllDenyAddress {
+    mail: String,
+    id: i64,
+    guild_id: String,
+}
+
+pub async fn get_all_deny_addresses(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(ListPath { guild_id, panel_id }): Path<ListPath>,
+) -> APIResult<Json<Vec<ResponseGetAllDenyAddress>>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    let mails = deny_db::get_all_deny_address(&state.pool, panel.id).await?;
+
+    Ok(Json(
+        mails
+            .iter()
+            .map(|(id, mail)| ResponseGetAllDenyAddress {
+                id: *id,
+                mail: mail.clone(),
+                guild_id: guild_id.to_string(),
+            })
+            .collect(),
+    ))
+}
+
+pub async fn delete_deny_address(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(DenyPath {
+        guild_id,
+        panel_id,
+        deny_id,
+    }): Path<DenyPath>,
 ) -> APIResult<()> {
     if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
         return Err(APIError::forbitten(
@@ -445,7 +1103,170 @@ pub async fn delete_mail_address(
         ));
     }
 
-    mail_db::delete_mail_address(&state.pool, guild_id as i64, mail_id).await?;
+    let panel = resolve_panel(&state, guild_id, panel_id).await?;
+    deny_db::delete_deny_address(&state.pool, panel.id, deny_id).await?;
 
     Ok(())
 }
+
+#[derive(Serialize)]
+pub struct Diagnostic {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    panel_id: Option<i64>,
+    #[serde(flatten)]
+    problem: Problem,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct ResponseDiagnostics {
+    problems: Vec<Diagnostic>,
+}
+
+/// Reports everything that would keep panels from being posted or roles from
+/// being granted in the guild.
+pub async fn get_diagnostics(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+) -> APIResult<Json<ResponseDiagnostics>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let permissions = BotPermissions::load(&state, guild_id).await?;
+    let mut problems = Vec::new();
+    let mut report = |panel_id: Option<i64>, problem: Problem| {
+        if problem == Problem::MissingManageRoles
+            && problems
+                .iter()
+                .any(|diagnostic: &Diagnostic| diagnostic.problem == problem)
+        {
+            return;
+        }
+        problems.push(Diagnostic {
+            panel_id,
+            message: problem.message(),
+            problem,
+        });
+    };
+    for panel in panel_db::get_panels(&state.pool, guild_id as i64).await? {
+        if let Some(problem) = permissions.check_role(panel.settings.role_id) {
+            report(Some(panel.id), problem);
+        }
+        if let Some(problem) = permissions
+            .check_panel_channel(&state, panel.settings.channel_id)
+            .await?
+        {
+            report(Some(panel.id), problem);
+        }
+        for (_, role_id) in tag_db::get_tag_roles(&state.pool, panel.id).await? {
+            if let Some(problem) = permissions.check_role(role_id) {
+                report(Some(panel.id), problem);
+            }
+        }
+    }
+
+    Ok(Json(ResponseDiagnostics { problems }))
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct RequestReconcile {
+    #[serde(default = "default_dry_run")]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct ResponseMemberChange {
+    user_id: String,
+    add_role_ids: Vec<String>,
+    remove_role_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseReconcile {
+    changes: Vec<ResponseMemberChange>,
+    /// Whether the changes are being applied, false for a dry run.
+    started: bool,
+}
+
+/// Re-evaluates every stored verification against the current rules and reports
+/// the role changes. Unless `dry_run` is set, the changes are then applied in the
+/// background; their progress is served by `get_reconcile_progress`.
+pub async fn reconcile_roles(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+    Json(body): Json<RequestReconcile>,
+) -> APIResult<Json<ResponseReconcile>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+    let claim = if body.dry_run {
+        None
+    } else {
+        let claim = reconcile::claim(&state, guild_id as i64)
+            .await?
+            .ok_or_else(|| APIError::badrequest("Reconciliation is already running"))?;
+        Some(claim)
+    };
+
+    let plan = match reconcile::plan(&state, guild_id as i64).await {
+        Ok(plan) => plan,
+        Err(error) => {
+            if let Some(claim) = claim {
+                lock::release(&state, claim).await?;
+            }
+            return Err(error.into());
+        }
+    };
+    let to_strings = |role_ids: &[i64]| role_ids.iter().map(i64::to_string).collect();
+    let changes = plan
+        .changes
+        .iter()
+        .map(|change| ResponseMemberChange {
+            user_id: change.user_id.to_string(),
+            add_role_ids: to_strings(&change.add),
+            remove_role_ids: to_strings(&change.remove),
+        })
+        .collect();
+    if let Some(claim) = claim {
+        tokio::spawn(reconcile::apply(
+            Arc::clone(&state),
+            guild_id as i64,
+            plan,
+            claim,
+        ));
+    }
+
+    Ok(Json(ResponseReconcile {
+        changes,
+        started: !body.dry_run,
+    }))
+}
+
+pub async fn get_reconcile_progress(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(guild_id): Path<u64>,
+) -> APIResult<Json<Progress>> {
+    if !permission_checker(Arc::clone(&state), guild_id, token.user_id).await? {
+        return Err(APIError::forbitten(
+            "You don't have permission to access this guild",
+        ));
+    }
+
+    let progress = reconcile::get_progress(&state, guild_id as i64)
+        .await?
+        .ok_or_else(|| APIError::notfound("Not found"))?;
+
+    Ok(Json(progress))
+}
@@ -0,0 +1,203 @@
+use crate::bot;
+use crate::server::result::{APIError, APIResult};
+use crate::utils::state::AppState;
+
+use std::env;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::extract::{Json, State};
+use axum::http::HeaderMap;
+use once_cell::sync::Lazy;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use twilight_model::application::interaction::{Interaction, InteractionType};
+use twilight_model::http::interaction::{InteractionResponse, InteractionResponseType};
+
+static DISCORD_PUBLIC_KEY: Lazy<Option<Vec<u8>>> = Lazy::new(|| {
+    env::var("DISCORD_PUBLIC_KEY")
+        .ok()
+        .and_then(|key| hex::decode(key.trim()).ok())
+});
+
+/// Requests signed longer ago than this are rejected as replays.
+const MAX_TIMESTAMP_AGE_SECS: u64 = 5 * 60;
+
+fn verify_signature(public_key: &[u8], headers: &HeaderMap, body: &[u8]) -> bool {
+    let (Some(signature), Some(timestamp)) = (
+        headers
+            .get("X-Signature-Ed25519")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| hex::decode(value).ok()),
+        headers
+            .get("X-Signature-Timestamp")
+            .and_then(|value| value.to_str().ok()),
+    ) else {
+        return false;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    if !timestamp
+        .parse::<u64>()
+        .is_ok_and(|timestamp| now.abs_diff(timestamp) <= MAX_TIMESTAMP_AGE_SECS)
+    {
+        return false;
+    }
+
+    let message = [timestamp.as_bytes(), body].concat();
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&message, &signature)
+        .is_ok()
+}
+
+/// Interactions endpoint URL of the application, receives button clicks over HTTP
+/// instead of the gateway.
+pub async fn receive_interaction(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> APIResult<Json<InteractionResponse>> {
+    let public_key = DISCORD_PUBLIC_KEY
+        .as_deref()
+        .ok_or_else(|| APIError::notfound("DISCORD_PUBLIC_KEY is not set"))?;
+    if !verify_signature(public_key, &headers, &body) {
+        return Err(APIError::unauthorized("Invalid request signature"));
+    }
+    let interaction: Interaction = serde_json::from_slice(&body)
+        .map_err(|error| APIError::badrequest(&format!("Invalid interaction: {}", error)))?;
+
+    if interaction.kind == InteractionType::Ping {
+        return Ok(Json(InteractionResponse {
+            kind: InteractionResponseType::Pong,
+            data: None,
+        }));
+    }
+    match bot::handle_interaction(&state, &interaction).await? {
+        Some(response) => Ok(Json(response)),
+        None => Err(APIError::badrequest("Unknown interaction")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::HeaderValue;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    const BODY: &[u8] = br#"{"type":1}"#;
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn signed_headers(key_pair: &Ed25519KeyPair, timestamp: u64, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let signature = key_pair.sign(&[timestamp.as_bytes(), body].concat());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Signature-Ed25519",
+            HeaderValue::from_str(&hex::encode(signature.as_ref())).unwrap(),
+        );
+        headers.insert(
+            "X-Signature-Timestamp",
+            HeaderValue::from_str(&timestamp).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let key_pair = key_pair();
+        let headers = signed_headers(&key_pair, now(), BODY);
+        assert!(verify_signature(
+            key_pair.public_key().as_ref(),
+            &headers,
+            BODY
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_body() {
+        let key_pair = key_pair();
+        let headers = signed_headers(&key_pair, now(), BODY);
+        assert!(!verify_signature(
+            key_pair.public_key().as_ref(),
+            &headers,
+            br#"{"type":2}"#
+        ));
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let other = Ed25519KeyPair::from_seed_unchecked(&[8; 32]).unwrap();
+        let headers = signed_headers(&other, now(), BODY);
+        assert!(!verify_signature(
+            key_pair().public_key().as_ref(),
+            &headers,
+            BODY
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_hex() {
+        let key_pair = key_pair();
+        let mut headers = signed_headers(&key_pair, now(), BODY);
+        headers.insert("X-Signature-Ed25519", HeaderValue::from_static("zz"));
+        assert!(!verify_signature(
+            key_pair.public_key().as_ref(),
+            &headers,
+            BODY
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_headers() {
+        let key_pair = key_pair();
+        let public_key = key_pair.public_key().as_ref();
+        for name in ["X-Signature-Ed25519", "X-Signature-Timestamp"] {
+            let mut headers = signed_headers(&key_pair, now(), BODY);
+            headers.remove(name);
+            assert!(!verify_signature(public_key, &headers, BODY), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_timestamp_outside_window() {
+        let key_pair = key_pair();
+        let public_key = key_pair.public_key().as_ref();
+        for timestamp in [
+            now() - MAX_TIMESTAMP_AGE_SECS - 10,
+            now() + MAX_TIMESTAMP_AGE_SECS + 10,
+        ] {
+            let headers = signed_headers(&key_pair, timestamp, BODY);
+            assert!(!verify_signature(public_key, &headers, BODY));
+        }
+    }
+
+    #[test]
+    fn rejects_non_numeric_timestamp() {
+        let key_pair = key_pair();
+        let signature = key_pair.sign(&[b"soon".as_slice(), BODY].concat());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Signature-Ed25519",
+            HeaderValue::from_str(&hex::encode(signature.as_ref())).unwrap(),
+        );
+        headers.insert("X-Signature-Timestamp", HeaderValue::from_static("soon"));
+        assert!(!verify_signature(
+            key_pair.public_key().as_ref(),
+            &headers,
+            BODY
+        ));
+    }
+}
@@ -1,17 +1,55 @@
+pub mod admin;
 pub mod auth;
 pub mod dashboard;
+pub mod interactions;
 
+use crate::utils::permission;
 use crate::AppState;
-use axum::{extract::State, Json};
-use serde::Serialize;
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+#[derive(Deserialize)]
+pub struct GetInviteUrlQuery {
+    /// Guild to pre-select on the invite screen.
+    pub guild_id: Option<u64>,
+    /// Also requests Manage Threads so panel posts in forums can be pinned.
+    #[serde(default)]
+    pub forum_pin: bool,
+}
+
+#[derive(Serialize)]
+pub struct InvitePermission {
+    pub name: &'static str,
+    pub reason: &'static str,
+}
+
 #[derive(Serialize)]
 pub struct GetInviteUrlResponse {
     pub url: String,
+    pub permissions: Vec<InvitePermission>,
 }
 
-pub async fn invite_url(State(state): State<Arc<AppState>>) -> Json<GetInviteUrlResponse> {
-    let invite_url = format!("https://discord.com/oauth2/authorize?client_id={}&permissions=8&integration_type=0&scope=bot", state.application_id);
-    Json(GetInviteUrlResponse { url: invite_url })
+pub async fn invite_url(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<GetInviteUrlQuery>,
+) -> Json<GetInviteUrlResponse> {
+    let requested = permission::invite_permissions(query.forum_pin);
+    let mut invite_url = format!(
+        "https://discord.com/oauth2/authorize?client_id={}&permissions={}&integration_type=0&scope=bot",
+        state.application_id,
+        permission::permission_bits(&requested).bits()
+    );
+    if let Some(guild_id) = query.guild_id {
+        invite_url.push_str(&format!("&guild_id={}&disable_guild_select=true", guild_id));
+    }
+    let permissions = requested
+        .into_iter()
+        .map(|(_, name, reason)| InvitePermission { name, reason })
+        .collect();
+    Json(GetInviteUrlResponse {
+        url: invite_url,
+        permissions,
+    })
 }
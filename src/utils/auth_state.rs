@@ -0,0 +1,156 @@
+use crate::utils::state::AppState;
+
+use std::env;
+
+use base64::prelude::*;
+use bb8_redis::redis::{self, AsyncCommands};
+use getrandom::getrandom;
+use once_cell::sync::Lazy;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+static DISCORD_CLIENT_ID: Lazy<String> = Lazy::new(|| env::var("DISCORD_CLIENT_ID").unwrap());
+pub static REDIRECT_URI: Lazy<String> =
+    Lazy::new(|| format!("{}/auth/callback/discord", env::var("BASE_URL").unwrap()));
+
+const STATE_TTL_SECS: u64 = 60 * 5;
+/// Callbacks allowed per state, failed ones included. Further ones are refused
+/// until the state expires.
+const MAX_ATTEMPTS: i64 = 5;
+/// How long a callback holds the state. A concurrent callback is refused meanwhile.
+const CLAIM_TTL_MS: u64 = 60 * 1000;
+
+/// Claims the state unless it's missing, locked or held by another callback,
+/// counting the attempt. KEYS: state, claim, attempts. ARGV: claim TTL (ms),
+/// max attempts, state TTL (s).
+const CLAIM_SCRIPT: &str = r"
+local data = redis.call('GET', KEYS[1])
+if not data then
+    return {'not_found'}
+end
+if tonumber(redis.call('GET', KEYS[3]) or '0') >= tonumber(ARGV[2]) then
+    return {'locked'}
+end
+if not redis.call('SET', KEYS[2], '1', 'NX', 'PX', ARGV[1]) then
+    return {'in_use'}
+end
+if redis.call('INCR', KEYS[3]) == 1 then
+    redis.call('EXPIRE', KEYS[3], ARGV[3])
+end
+return {'claimed', data}
+";
+
+/// What a verification link was issued for, stored under `auth:{state}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthState {
+    pub user_id: u64,
+    pub guild_id: i64,
+    pub panel_id: i64,
+    /// PKCE verifier of the authorization request. Only a code issued for that
+    /// request can be exchanged with it.
+    pub code_verifier: String,
+}
+
+pub enum Claim {
+    Claimed(AuthState),
+    NotFound,
+    InUse,
+    Locked,
+}
+
+fn state_keys(state: &str) -> [String; 3] {
+    [
+        format!("auth:{}", state),
+        format!("auth:{}:claim", state),
+        format!("auth:{}:attempts", state),
+    ]
+}
+
+/// Stores a new state for the member and returns the Discord authorization URL
+/// bound to it.
+pub async fn create(
+    state: &AppState,
+    user_id: u64,
+    guild_id: i64,
+    panel_id: i64,
+) -> anyhow::Result<Url> {
+    let code = Uuid::new_v4();
+    let mut verifier = [0; 32];
+    getrandom(&mut verifier)?;
+    let auth = AuthState {
+        user_id,
+        guild_id,
+        panel_id,
+        code_verifier: BASE64_URL_SAFE_NO_PAD.encode(verifier),
+    };
+    let challenge = BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, auth.code_verifier.as_bytes()));
+    let mut url = Url::parse("https://discord.com/oauth2/authorize")?;
+    url.query_pairs_mut()
+        .append_pair("client_id", &DISCORD_CLIENT_ID)
+        .append_pair("response_type", "code")
+        .append_pair("redirect_uri", &REDIRECT_URI)
+        .append_pair("scope", "identify email")
+        .append_pair("state", &code.to_string())
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    let mut conn = state.redis.get().await?;
+    conn.set_ex::<_, _, ()>(
+        format!("auth:{}", code),
+        serde_json::to_string(&auth)?,
+        STATE_TTL_SECS,
+    )
+    .await?;
+    Ok(url)
+}
+
+/// Reads the state without claiming it, e.g. to rate limit its member first.
+pub async fn peek(state: &AppState, code: &str) -> anyhow::Result<Option<AuthState>> {
+    let mut conn = state.redis.get().await?;
+    let data: Option<String> = conn.get(format!("auth:{}", code)).await?;
+    // States from before the format change can't be used anymore.
+    Ok(data.and_then(|data| serde_json::from_str(&data).ok()))
+}
+
+/// Atomically claims the state for one callback. The callback must then either
+/// `consume` or `release` it.
+pub async fn claim(state: &AppState, code: &str) -> anyhow::Result<Claim> {
+    let [key, claim_key, attempts_key] = state_keys(code);
+    let mut conn = state.redis.get().await?;
+    let result: Vec<String> = redis::cmd("EVAL")
+        .arg(CLAIM_SCRIPT)
+        .arg(3)
+        .arg(&[key, claim_key, attempts_key])
+        .arg(CLAIM_TTL_MS)
+        .arg(MAX_ATTEMPTS)
+        .arg(STATE_TTL_SECS)
+        .query_async(&mut *conn)
+        .await?;
+    Ok(match result.first().map(String::as_str) {
+        Some("claimed") => match result.get(1).map(|data| serde_json::from_str(data)) {
+            Some(Ok(auth)) => Claim::Claimed(auth),
+            // States from before the format change can't be used anymore.
+            _ => Claim::NotFound,
+        },
+        Some("locked") => Claim::Locked,
+        Some("in_use") => Claim::InUse,
+        _ => Claim::NotFound,
+    })
+}
+
+/// Deletes the state after a successful verification so it can't be replayed.
+pub async fn consume(state: &AppState, code: &str) -> anyhow::Result<()> {
+    let mut conn = state.redis.get().await?;
+    conn.del::<_, ()>(&state_keys(code)).await?;
+    Ok(())
+}
+
+/// Lets the member retry after a failed callback, as long as attempts are left.
+pub async fn release(state: &AppState, code: &str) -> anyhow::Result<()> {
+    let [_, claim_key, _] = state_keys(code);
+    let mut conn = state.redis.get().await?;
+    conn.del::<_, ()>(claim_key).await?;
+    Ok(())
+}
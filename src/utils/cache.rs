@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use twilight_gateway::Event;
+use twilight_model::channel::Channel;
+use twilight_model::guild::Role;
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker};
+use twilight_model::id::Id;
+
+struct CachedGuild {
+    owner_id: Id<UserMarker>,
+    preferred_locale: String,
+    roles: HashMap<Id<RoleMarker>, Role>,
+    /// Channels and active threads.
+    channels: HashMap<Id<ChannelMarker>, Channel>,
+    /// Role IDs of the members seen so far.
+    members: HashMap<Id<UserMarker>, Vec<Id<RoleMarker>>>,
+}
+
+/// Guilds, roles, channels and member roles kept up to date from gateway events.
+/// Only guilds announced by the gateway are cached, so lookups return `None` for
+/// everything else (or on instances running without a gateway) and callers fall
+/// back to REST.
+#[derive(Default)]
+pub struct DiscordCache(RwLock<HashMap<Id<GuildMarker>, CachedGuild>>);
+
+impl DiscordCache {
+    pub fn update(&self, event: &Event) {
+        let mut guilds = self.0.write().unwrap();
+        match event {
+            Event::GuildCreate(guild) => {
+                let guild = &guild.0;
+                let mut channels = HashMap::new();
+                for channel in guild.channels.iter().chain(&guild.threads) {
+                    let mut channel = channel.clone();
+                    channel.guild_id = Some(guild.id);
+                    channels.insert(channel.id, channel);
+                }
+                guilds.insert(
+                    guild.id,
+                    CachedGuild {
+                        owner_id: guild.owner_id,
+                        preferred_locale: guild.preferred_locale.clone(),
+                        roles: guild
+                            .roles
+                            .iter()
+                            .map(|role| (role.id, role.clone()))
+                            .collect(),
+                        channels,
+                        members: guild
+                            .members
+                            .iter()
+                            .map(|member| (member.user.id, member.roles.clone()))
+                            .collect(),
+                    },
+                );
+            }
+            Event::GuildUpdate(update) => {
+                if let Some(guild) = guilds.get_mut(&update.0.id) {
+                    guild.owner_id = update.0.owner_id;
+                    guild.preferred_locale = update.0.preferred_locale.clone();
+                    guild.roles = update
+                        .0
+                        .roles
+                        .iter()
+                        .map(|role| (role.id, role.clone()))
+                        .collect();
+                }
+            }
+            Event::GuildDelete(delete) => {
+                guilds.remove(&delete.id);
+            }
+            Event::RoleCreate(create) => {
+                if let Some(guild) = guilds.get_mut(&create.guild_id) {
+                    guild.roles.insert(create.role.id, create.role.clone());
+                }
+            }
+            Event::RoleUpdate(update) => {
+                if let Some(guild) = guilds.get_mut(&update.guild_id) {
+                    guild.roles.insert(update.role.id, update.role.clone());
+                }
+            }
+            Event::RoleDelete(delete) => {
+                if let Some(guild) = guilds.get_mut(&delete.guild_id) {
+                    guild.roles.remove(&delete.role_id);
+                    for roles in guild.members.values_mut() {
+                        roles.retain(|role_id| *role_id != delete.role_id);
+                    }
+                }
+            }
+            Event::ChannelCreate(channel) => insert_channel(&mut guilds, &channel.0),
+            Event::ChannelUpdate(channel) => insert_channel(&mut guilds, &channel.0),
+            Event::ThreadCreate(thread) => insert_channel(&mut guilds, &thread.0),
+            Event::ThreadUpdate(thread) => {
+                let archived = thread
+                    .0
+                    .thread_metadata
+                    .as_ref()
+                    .is_some_and(|metadata| metadata.archived);
+                if archived {
+                    remove_channel(&mut guilds, thread.0.guild_id, thread.0.id);
+                } else {
+                    insert_channel(&mut guilds, &thread.0);
+                }
+            }
+            Event::ChannelDelete(channel) => {
+                remove_channel(&mut guilds, channel.0.guild_id, channel.0.id)
+            }
+            Event::ThreadDelete(thread) => {
+                remove_channel(&mut guilds, Some(thread.guild_id), thread.id)
+            }
+            Event::ThreadListSync(sync) => {
+                if let Some(guild) = guilds.get_mut(&sync.guild_id) {
+                    for thread in &sync.threads {
+                        let mut thread = thread.clone();
+                        thread.guild_id = Some(sync.guild_id);
+                        guild.channels.insert(thread.id, thread);
+                    }
+                }
+            }
+            Event::MemberAdd(add) => {
+                if let Some(guild) = guilds.get_mut(&add.guild_id) {
+                    guild
+                        .members
+                        .insert(add.member.user.id, add.member.roles.clone());
+                }
+            }
+            Event::MemberUpdate(update) => {
+                if let Some(guild) = guilds.get_mut(&update.guild_id) {
+                    guild.members.insert(update.user.id, update.roles.clone());
+                }
+            }
+            Event::MemberChunk(chunk) => {
+                if let Some(guild) = guilds.get_mut(&chunk.guild_id) {
+                    for member in &chunk.members {
+                        guild.members.insert(member.user.id, member.roles.clone());
+                    }
+                }
+            }
+            Event::MemberRemove(remove) => {
+                if let Some(guild) = guilds.get_mut(&remove.guild_id) {
+                    guild.members.remove(&remove.user.id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn owner_id(&self, guild_id: Id<GuildMarker>) -> Option<Id<UserMarker>> {
+        Some(self.0.read().unwrap().get(&guild_id)?.owner_id)
+    }
+
+    pub fn preferred_locale(&self, guild_id: Id<GuildMarker>) -> Option<String> {
+        Some(
+            self.0
+                .read()
+                .unwrap()
+                .get(&guild_id)?
+                .preferred_locale
+                .clone(),
+        )
+    }
+
+    pub fn roles(&self, guild_id: Id<GuildMarker>) -> Option<Vec<Role>> {
+        let guilds = self.0.read().unwrap();
+        let mut roles = guilds
+            .get(&guild_id)?
+            .roles
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        roles.sort_by_key(|role| (role.position, role.id));
+        Some(roles)
+    }
+
+    pub fn channels(&self, guild_id: Id<GuildMarker>) -> Option<Vec<Channel>> {
+        let guilds = self.0.read().unwrap();
+        let mut channels = guilds
+            .get(&guild_id)?
+            .channels
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort_by_key(|channel| (channel.position, channel.id));
+        Some(channels)
+    }
+
+    pub fn channel(
+        &self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> Option<Channel> {
+        self.0
+            .read()
+            .unwrap()
+            .get(&guild_id)?
+            .channels
+            .get(&channel_id)
+            .cloned()
+    }
+
+    pub fn member_roles(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Option<Vec<Id<RoleMarker>>> {
+        self.0
+            .read()
+            .unwrap()
+            .get(&guild_id)?
+            .members
+            .get(&user_id)
+            .cloned()
+    }
+
+    /// Remembers member roles fetched over REST, if the guild is cached. Later
+    /// changes arrive as `MemberUpdate` events, which need the `GUILD_MEMBERS`
+    /// intent, so callers must only use this while member events are enabled.
+    pub fn insert_member(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        roles: Vec<Id<RoleMarker>>,
+    ) {
+        if let Some(guild) = self.0.write().unwrap().get_mut(&guild_id) {
+            guild.members.insert(user_id, roles);
+        }
+    }
+}
+
+fn insert_channel(guilds: &mut HashMap<Id<GuildMarker>, CachedGuild>, channel: &Channel) {
+    if let Some(guild) = channel
+        .guild_id
+        .and_then(|guild_id| guilds.get_mut(&guild_id))
+    {
+        guild.channels.insert(channel.id, channel.clone());
+    }
+}
+
+fn remove_channel(
+    guilds: &mut HashMap<Id<GuildMarker>, CachedGuild>,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+) {
+    if let Some(guild) = guild_id.and_then(|guild_id| guilds.get_mut(&guild_id)) {
+        guild.channels.remove(&channel_id);
+    }
+}
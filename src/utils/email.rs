@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use url::Host;
+
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// Per-guild rules used to turn an address into its canonical form.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct NormalizeOptions {
+    pub lowercase_local_part: bool,
+    pub strip_plus_tag: bool,
+    pub ignore_gmail_dots: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            lowercase_local_part: true,
+            strip_plus_tag: false,
+            ignore_gmail_dots: false,
+        }
+    }
+}
+
+/// Lowercases a domain and converts it to its ASCII (punycode) form.
+pub fn normalize_domain(domain: &str) -> anyhow::Result<String> {
+    match Host::parse(domain.trim())? {
+        Host::Domain(domain) => Ok(domain),
+        _ => anyhow::bail!("Invalid email domain"),
+    }
+}
+
+fn normalize_local(local: &str, domain: &str, options: &NormalizeOptions) -> (String, String) {
+    let mut local = local.to_string();
+    let mut domain = domain.to_string();
+    if options.lowercase_local_part {
+        local = local.to_lowercase();
+    }
+    if options.strip_plus_tag {
+        if let Some((base, _)) = local.split_once('+') {
+            local = base.to_string();
+        }
+    }
+    if options.ignore_gmail_dots && GMAIL_DOMAINS.contains(&domain.as_str()) {
+        local = local.replace('.', "");
+        domain = GMAIL_DOMAINS[0].to_string();
+    }
+    (local, domain)
+}
+
+/// Canonicalizes an address. The domain is always normalized, the local part is
+/// rewritten according to `options`.
+pub fn normalize(email: &str, options: &NormalizeOptions) -> anyhow::Result<String> {
+    let (local, domain) = email
+        .trim()
+        .rsplit_once('@')
+        .ok_or_else(|| anyhow::anyhow!("Invalid email address"))?;
+    if local.is_empty() {
+        anyhow::bail!("Invalid email address");
+    }
+    let (local, domain) = normalize_local(local, &normalize_domain(domain)?, options);
+
+    Ok(format!("{}@{}", local, domain))
+}
+
+/// Returns every parent domain of `domain`, e.g. `a.example.jp` gives
+/// `example.jp` and `jp`.
+pub fn parent_domains(domain: &str) -> Vec<String> {
+    domain
+        .match_indices('.')
+        .map(|(index, _)| domain[index + 1..].to_string())
+        .collect()
+}
+
+/// A single allowlist entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailEntry {
+    /// `taro@example.jp`, stored normalized.
+    Address(String),
+    /// `@example.jp`, any address of exactly this domain.
+    Domain(String),
+    /// `*.example.jp`, any address of a subdomain of this domain.
+    Subdomain(String),
+    /// `s2025*@example.jp`, a local part glob (`*` and `?`) stored as a `LIKE` pattern.
+    LocalGlob { pattern: String, domain: String },
+}
+
+impl MailEntry {
+    pub fn parse(input: &str, options: &NormalizeOptions) -> anyhow::Result<Self> {
+        let input = input.trim();
+        if let Some(domain) = input.strip_prefix('@') {
+            return Ok(Self::Domain(normalize_domain(domain)?));
+        }
+        if let Some(domain) = input.strip_prefix("*.") {
+            if !domain.contains('@') {
+                return Ok(Self::Subdomain(normalize_domain(domain)?));
+            }
+        }
+        let (local, domain) = input
+            .rsplit_once('@')
+            .ok_or_else(|| anyhow::anyhow!("Invalid email address"))?;
+        if local.is_empty() {
+            anyhow::bail!("Invalid email address");
+        }
+        if local.contains(['*', '?']) {
+            let (local, domain) = normalize_local(local, &normalize_domain(domain)?, options);
+            let mut pattern = String::new();
+            for c in local.chars() {
+                match c {
+                    '*' => pattern.push('%'),
+                    '?' => pattern.push('_'),
+                    '%' | '_' | '\\' => {
+                        pattern.push('\\');
+                        pattern.push(c);
+                    }
+                    c => pattern.push(c),
+                }
+            }
+            return Ok(Self::LocalGlob { pattern, domain });
+        }
+        Ok(Self::Address(normalize(input, options)?))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Address(_) => "address",
+            Self::Domain(_) => "domain",
+            Self::Subdomain(_) => "subdomain",
+            Self::LocalGlob { .. } => "glob",
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        match self {
+            Self::Address(email) => email.rsplit_once('@').map_or("", |(_, domain)| domain),
+            Self::Domain(domain) | Self::Subdomain(domain) => domain,
+            Self::LocalGlob { domain, .. } => domain,
+        }
+    }
+
+    pub fn normalized_email(&self) -> &str {
+        match self {
+            Self::Address(email) => email,
+            _ => "",
+        }
+    }
+
+    pub fn local_pattern(&self) -> &str {
+        match self {
+            Self::LocalGlob { pattern, .. } => pattern,
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(strip_plus_tag: bool, ignore_gmail_dots: bool) -> NormalizeOptions {
+        NormalizeOptions {
+            lowercase_local_part: true,
+            strip_plus_tag,
+            ignore_gmail_dots,
+        }
+    }
+
+    #[test]
+    fn normalize_trims_and_lowercases() {
+        let normalized = normalize("  Taro.Yamada@Example.JP ", &NormalizeOptions::default());
+        assert_eq!(normalized.unwrap(), "taro.yamada@example.jp");
+    }
+
+    #[test]
+    fn normalize_keeps_local_case_when_disabled() {
+        let options = NormalizeOptions {
+            lowercase_local_part: false,
+            ..NormalizeOptions::default()
+        };
+        assert_eq!(
+            normalize("Taro@Example.JP", &options).unwrap(),
+            "Taro@example.jp"
+        );
+    }
+
+    #[test]
+    fn normalize_converts_idn_domains() {
+        assert_eq!(
+            normalize("taro@例え.jp", &NormalizeOptions::default()).unwrap(),
+            "taro@xn--r8jz45g.jp"
+        );
+        assert_eq!(
+            normalize("taro@EXAMPLE.xn--r8jz45g.jp", &NormalizeOptions::default()).unwrap(),
+            "taro@example.xn--r8jz45g.jp"
+        );
+    }
+
+    #[test]
+    fn normalize_strips_plus_tags() {
+        assert_eq!(
+            normalize("taro+news@example.jp", &options(true, false)).unwrap(),
+            "taro@example.jp"
+        );
+        assert_eq!(
+            normalize("taro+news@example.jp", &options(false, false)).unwrap(),
+            "taro+news@example.jp"
+        );
+    }
+
+    #[test]
+    fn normalize_ignores_gmail_dots() {
+        assert_eq!(
+            normalize("Ta.Ro@googlemail.com", &options(false, true)).unwrap(),
+            "taro@gmail.com"
+        );
+        assert_eq!(
+            normalize("ta.ro+x@gmail.com", &options(true, true)).unwrap(),
+            "taro@gmail.com"
+        );
+        // Other providers keep their dots.
+        assert_eq!(
+            normalize("ta.ro@example.jp", &options(false, true)).unwrap(),
+            "ta.ro@example.jp"
+        );
+    }
+
+    #[test]
+    fn normalize_rejects_invalid_addresses() {
+        let options = NormalizeOptions::default();
+        assert!(normalize("taro", &options).is_err());
+        assert!(normalize("@example.jp", &options).is_err());
+        assert!(normalize("taro@", &options).is_err());
+        assert!(normalize("taro@[127.0.0.1]", &options).is_err());
+    }
+}
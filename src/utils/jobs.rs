@@ -0,0 +1,199 @@
+use crate::db::job as db;
+use crate::utils::notify;
+use crate::utils::state::AppState;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use twilight_http::error::{Error as HttpError, ErrorType};
+use twilight_http::request::AuditLogReason;
+use twilight_model::id::Id;
+
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DONE_RETENTION_DAYS: i32 = 7;
+const BASE_BACKOFF_SECS: f64 = 2.0;
+const MAX_BACKOFF_SECS: f64 = 60.0 * 60.0;
+
+/// A Discord side effect run by the job workers. Role removals aren't jobs:
+/// reconciliation, the only place removing roles, paces its own requests and
+/// records which changes succeeded, see `reconcile::apply`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    AddRole {
+        guild_id: i64,
+        user_id: i64,
+        role_id: i64,
+        reason: String,
+    },
+    SendMessage {
+        channel_id: i64,
+        content: String,
+    },
+    DirectMessage {
+        user_id: i64,
+        content: String,
+    },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::AddRole { .. } => "add_role",
+            Self::SendMessage { .. } => "send_message",
+            Self::DirectMessage { .. } => "direct_message",
+        }
+    }
+
+    async fn run(&self, state: &AppState) -> anyhow::Result<()> {
+        match self {
+            Self::AddRole {
+                guild_id,
+                user_id,
+                role_id,
+                reason,
+            } => {
+                state
+                    .http
+                    .add_guild_member_role(
+                        Id::new(*guild_id as u64),
+                        Id::new(*user_id as u64),
+                        Id::new(*role_id as u64),
+                    )
+                    .reason(reason)?
+                    .await?;
+            }
+            Self::SendMessage {
+                channel_id,
+                content,
+            } => {
+                state
+                    .http
+                    .create_message(Id::new(*channel_id as u64))
+                    .content(content)?
+                    .await?;
+            }
+            Self::DirectMessage { user_id, content } => {
+                let channel = state
+                    .http
+                    .create_private_channel(Id::new(*user_id as u64))
+                    .await?
+                    .model()
+                    .await?;
+                state
+                    .http
+                    .create_message(channel.id)
+                    .content(content)?
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enqueues a job. `idempotency_key` keeps the same side effect from being
+/// enqueued twice, e.g. when a request is retried.
+pub async fn enqueue(
+    state: &AppState,
+    job: &Job,
+    idempotency_key: Option<&str>,
+) -> anyhow::Result<()> {
+    db::add_job(
+        &state.pool,
+        job.kind(),
+        &serde_json::to_string(job)?,
+        idempotency_key,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Whether retrying can't help, e.g. missing permissions or an unknown member.
+/// Rate limits are waited out by the HTTP client and server errors are retried.
+fn is_permanent(error: &anyhow::Error) -> bool {
+    let Some(error) = error.downcast_ref::<HttpError>() else {
+        return false;
+    };
+    matches!(
+        error.kind(),
+        ErrorType::Response { status, .. } if status.is_client_error() && status.get() != 429
+    )
+}
+
+/// Tells the guild's admins that a member didn't get a role. Failed messages
+/// aren't reported, the report would be a message too.
+async fn job_died(state: &AppState, job: &Job, error: &anyhow::Error) {
+    if let Job::AddRole {
+        guild_id,
+        user_id,
+        role_id,
+        ..
+    } = job
+    {
+        let content = format!(
+            "The role {} couldn't be given to member {}: {}",
+            role_id, user_id, error
+        );
+        notify::notify_admins(state, *guild_id, &content).await;
+    }
+}
+
+async fn run_next(state: &AppState) -> anyhow::Result<bool> {
+    let Some(claimed) = db::claim_job(&state.pool).await? else {
+        return Ok(false);
+    };
+    let job = serde_json::from_str::<Job>(&claimed.payload);
+    let result = match &job {
+        Ok(job) => job.run(state).await,
+        Err(error) => Err(anyhow::anyhow!("Invalid payload: {}", error)),
+    };
+    match result {
+        Ok(()) => db::complete_job(&state.pool, claimed.id).await?,
+        Err(error) if is_permanent(&error) || claimed.attempts >= claimed.max_attempts => {
+            tracing::warn!("Job {} failed permanently: {}", claimed.id, error);
+            db::kill_job(&state.pool, claimed.id, &error.to_string()).await?;
+            if let Ok(job) = &job {
+                job_died(state, job, &error).await;
+            }
+        }
+        Err(error) => {
+            let delay = (BASE_BACKOFF_SECS * 2f64.powi(claimed.attempts - 1)).min(MAX_BACKOFF_SECS);
+            tracing::info!(
+                "Job {} failed, retrying in {}s: {}",
+                claimed.id,
+                delay,
+                error
+            );
+            db::retry_job_later(&state.pool, claimed.id, &error.to_string(), delay).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// Runs jobs until the process exits. Several workers, also across processes,
+/// can run side by side.
+pub async fn run_worker(state: Arc<AppState>) {
+    loop {
+        match run_next(&state).await {
+            Ok(true) => {}
+            Ok(false) => tokio::time::sleep(IDLE_INTERVAL).await,
+            Err(error) => {
+                tracing::warn!("Job worker error: {}", error);
+                tokio::time::sleep(IDLE_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Deletes finished jobs once a week old.
+pub async fn run_purge(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(error) = db::purge_done_jobs(&state.pool, DONE_RETENTION_DAYS).await {
+            tracing::warn!("Failed to purge done jobs: {}", error);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::utils::state::AppState;
+
+use bb8_redis::redis::{self, AsyncCommands};
+use uuid::Uuid;
+
+/// Renews the lock if it's still held by the token. KEYS: lock. ARGV: token, TTL (s).
+const RENEW_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+";
+
+/// Deletes the lock if it's still held by the token. KEYS: lock. ARGV: token.
+const RELEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// A named lock shared by every instance. It expires after its TTL unless renewed,
+/// so a crashed holder doesn't keep it.
+pub struct Lock {
+    key: String,
+    token: String,
+}
+
+fn lock_key(name: &str) -> String {
+    format!("lock:{}", name)
+}
+
+/// Takes the lock for `ttl_secs` unless it's already held.
+pub async fn try_lock(state: &AppState, name: &str, ttl_secs: u64) -> anyhow::Result<Option<Lock>> {
+    let lock = Lock {
+        key: lock_key(name),
+        token: Uuid::new_v4().to_string(),
+    };
+    let mut conn = state.redis.get().await?;
+    let locked: Option<String> = redis::cmd("SET")
+        .arg(&lock.key)
+        .arg(&lock.token)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl_secs)
+        .query_async(&mut *conn)
+        .await?;
+    Ok(locked.map(|_| lock))
+}
+
+/// Extends the lock by `ttl_secs`. Returns false if it expired and was lost.
+pub async fn renew(state: &AppState, lock: &Lock, ttl_secs: u64) -> anyhow::Result<bool> {
+    let mut conn = state.redis.get().await?;
+    let renewed: i64 = redis::cmd("EVAL")
+        .arg(RENEW_SCRIPT)
+        .arg(1)
+        .arg(&lock.key)
+        .arg(&lock.token)
+        .arg(ttl_secs)
+        .query_async(&mut *conn)
+        .await?;
+    Ok(renewed == 1)
+}
+
+pub async fn release(state: &AppState, lock: Lock) -> anyhow::Result<()> {
+    let mut conn = state.redis.get().await?;
+    redis::cmd("EVAL")
+        .arg(RELEASE_SCRIPT)
+        .arg(1)
+        .arg(&lock.key)
+        .arg(&lock.token)
+        .query_async::<()>(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn is_locked(state: &AppState, name: &str) -> anyhow::Result<bool> {
+    let mut conn = state.redis.get().await?;
+    Ok(conn.exists(lock_key(name)).await?)
+}
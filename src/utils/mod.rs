@@ -1 +1,17 @@
+pub mod auth_state;
+pub mod cache;
+pub mod email;
+pub mod jobs;
+pub mod lock;
+pub mod notify;
+pub mod panel;
+pub mod pattern;
+pub mod permission;
+pub mod provider;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod retention;
+pub mod roles;
+pub mod rules;
+pub mod shard;
 pub mod state;
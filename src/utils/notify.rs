@@ -0,0 +1,34 @@
+use crate::db::verify as verify_db;
+use crate::utils::jobs::{self, Job};
+use crate::utils::state::AppState;
+
+use twilight_model::id::Id;
+
+async fn send(state: &AppState, guild_id: i64, content: &str) -> anyhow::Result<()> {
+    let job = match verify_db::get_log_channel(&state.pool, guild_id).await? {
+        Some(channel_id) => Job::SendMessage {
+            channel_id,
+            content: content.to_string(),
+        },
+        None => {
+            let guild_id = Id::new(guild_id as u64);
+            let owner_id = match state.cache.owner_id(guild_id) {
+                Some(owner_id) => owner_id,
+                None => state.http.guild(guild_id).await?.model().await?.owner_id,
+            };
+            Job::DirectMessage {
+                user_id: owner_id.get() as i64,
+                content: content.to_string(),
+            }
+        }
+    };
+    jobs::enqueue(state, &job, None).await
+}
+
+/// Tells the guild's admins about a problem with its settings, in the log channel
+/// if one is set or else by DM to the owner. Failures are only logged.
+pub async fn notify_admins(state: &AppState, guild_id: i64, content: &str) {
+    if let Err(error) = send(state, guild_id, content).await {
+        tracing::warn!("Failed to notify admins of {}: {}", guild_id, error);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::db::panel::{self as db, Panel};
+use crate::utils::state::AppState;
+
+use serde::{Deserialize, Serialize};
+use sparkle_interactions::builder::component::{ButtonBuilder, ComponentsBuilder};
+use twilight_http::error::{Error as HttpError, ErrorType};
+use twilight_model::channel::message::component::{ButtonStyle, Component};
+use twilight_model::channel::message::Embed;
+use twilight_model::channel::{ChannelFlags, ChannelType};
+use twilight_model::id::Id;
+use twilight_util::builder::embed::{EmbedBuilder, ImageSource};
+
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_LABEL_LENGTH: usize = 80;
+const MAX_MESSAGE_LENGTH: usize = 2000;
+const MAX_FORUM_POST_TITLE_LENGTH: usize = 100;
+
+const AUTH_CUSTOM_ID: &str = "auth";
+
+/// Button `custom_id` of a panel, `auth:<panel_id>`.
+pub fn custom_id(panel_id: i64) -> String {
+    format!("{}:{}", AUTH_CUSTOM_ID, panel_id)
+}
+
+/// Parses a panel button `custom_id`. Returns `Some(None)` for the plain `auth` of
+/// buttons posted before panels had their own ID.
+pub fn parse_custom_id(custom_id: &str) -> Option<Option<i64>> {
+    if custom_id == AUTH_CUSTOM_ID {
+        return Some(None);
+    }
+    let (prefix, panel_id) = custom_id.split_once(':')?;
+    if prefix != AUTH_CUSTOM_ID {
+        return None;
+    }
+    panel_id.parse().ok().map(Some)
+}
+
+/// Built-in texts for one language.
+struct DefaultTexts {
+    title: &'static str,
+    description: &'static str,
+    button_label: &'static str,
+    reply_message: &'static str,
+    link_label: &'static str,
+    success_message: &'static str,
+    failure_message: &'static str,
+    cooldown_message: &'static str,
+}
+
+const JA: DefaultTexts = DefaultTexts {
+    title: "認証パネル",
+    description: "ボタンをクリックすると認証が始まります。",
+    button_label: "認証する",
+    reply_message: "認証を開始します。\n以下のボタンをクリックして飛んでください。",
+    link_label: "認証ページへ",
+    success_message: "認証が完了しました。",
+    failure_message: "認証に失敗しました。",
+    cooldown_message: "試行回数が多すぎます。{seconds}秒後にもう一度お試しください。",
+};
+
+const EN: DefaultTexts = DefaultTexts {
+    title: "Verification",
+    description: "Click the button below to start verification.",
+    button_label: "Verify",
+    reply_message: "Starting verification.\nClick the button below to continue.",
+    link_label: "Go to verification page",
+    success_message: "Verification completed.",
+    failure_message: "Verification failed.",
+    cooldown_message: "Too many attempts. Please try again in {seconds} seconds.",
+};
+
+const KO: DefaultTexts = DefaultTexts {
+    title: "인증 패널",
+    description: "버튼을 클릭하면 인증이 시작됩니다.",
+    button_label: "인증하기",
+    reply_message: "인증을 시작합니다.\n아래 버튼을 클릭하여 이동하세요.",
+    link_label: "인증 페이지로",
+    success_message: "인증이 완료되었습니다.",
+    failure_message: "인증에 실패했습니다.",
+    cooldown_message: "시도 횟수가 너무 많습니다. {seconds}초 후에 다시 시도해 주세요.",
+};
+
+const ZH_CN: DefaultTexts = DefaultTexts {
+    title: "验证面板",
+    description: "点击按钮开始验证。",
+    button_label: "验证",
+    reply_message: "开始验证。\n请点击下方按钮继续。",
+    link_label: "前往验证页面",
+    success_message: "验证完成。",
+    failure_message: "验证失败。",
+    cooldown_message: "尝试次数过多，请在{seconds}秒后重试。",
+};
+
+const ZH_TW: DefaultTexts = DefaultTexts {
+    title: "驗證面板",
+    description: "點擊按鈕開始驗證。",
+    button_label: "驗證",
+    reply_message: "開始驗證。\n請點擊下方按鈕繼續。",
+    link_label: "前往驗證頁面",
+    success_message: "驗證完成。",
+    failure_message: "驗證失敗。",
+    cooldown_message: "嘗試次數過多，請在{seconds}秒後重試。",
+};
+
+/// Picks the built-in texts for a Discord locale such as `ja` or `en-US`.
+fn default_texts(locale: &str) -> &'static DefaultTexts {
+    match locale {
+        "ja" => &JA,
+        "ko" => &KO,
+        "zh-CN" => &ZH_CN,
+        "zh-TW" => &ZH_TW,
+        _ => &EN,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PanelButtonStyle {
+    Primary,
+    Secondary,
+    #[default]
+    Success,
+    Danger,
+}
+
+impl From<PanelButtonStyle> for ButtonStyle {
+    fn from(style: PanelButtonStyle) -> Self {
+        match style {
+            PanelButtonStyle::Primary => ButtonStyle::Primary,
+            PanelButtonStyle::Secondary => ButtonStyle::Secondary,
+            PanelButtonStyle::Success => ButtonStyle::Success,
+            PanelButtonStyle::Danger => ButtonStyle::Danger,
+        }
+    }
+}
+
+/// Per-guild overrides for every user-facing text of the verification flow.
+/// Unset texts fall back to the built-in ones of `locale`, or of the guild's
+/// (or member's) locale when `locale` is unset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PanelContent {
+    pub locale: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<u32>,
+    pub image_url: Option<String>,
+    pub button_label: Option<String>,
+    pub button_style: PanelButtonStyle,
+    pub reply_message: Option<String>,
+    pub link_label: Option<String>,
+    pub success_message: Option<String>,
+    pub failure_message: Option<String>,
+    /// Reply when a member presses the button too often. `{seconds}` is
+    /// replaced with the wait.
+    pub cooldown_message: Option<String>,
+}
+
+/// Texts with every override and default applied.
+pub struct PanelTexts {
+    pub title: String,
+    pub description: String,
+    pub button_label: String,
+    pub reply_message: String,
+    pub link_label: String,
+    pub success_message: String,
+    pub failure_message: String,
+    pub cooldown_message: String,
+}
+
+impl PanelTexts {
+    /// The cooldown reply with the wait filled in.
+    pub fn cooldown_message(&self, seconds: u64) -> String {
+        self.cooldown_message
+            .replace("{seconds}", &seconds.to_string())
+    }
+}
+
+fn check_length(name: &str, text: &Option<String>, max: usize) -> anyhow::Result<()> {
+    if let Some(text) = text {
+        if text.is_empty() || text.chars().count() > max {
+            anyhow::bail!("{} must be between 1 and {} characters", name, max);
+        }
+    }
+    Ok(())
+}
+
+impl PanelContent {
+    pub fn from_panel(panel: &Panel) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(&panel.settings.content)?)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_length("title", &self.title, MAX_TITLE_LENGTH)?;
+        check_length("description", &self.description, MAX_DESCRIPTION_LENGTH)?;
+        check_length("button_label", &self.button_label, MAX_LABEL_LENGTH)?;
+        check_length("link_label", &self.link_label, MAX_LABEL_LENGTH)?;
+        check_length("reply_message", &self.reply_message, MAX_MESSAGE_LENGTH)?;
+        check_length("success_message", &self.success_message, MAX_MESSAGE_LENGTH)?;
+        check_length("failure_message", &self.failure_message, MAX_MESSAGE_LENGTH)?;
+        check_length(
+            "cooldown_message",
+            &self.cooldown_message,
+            MAX_MESSAGE_LENGTH,
+        )?;
+        if self.color.is_some_and(|color| color > 0xFF_FF_FF) {
+            anyhow::bail!("color must be a 24-bit RGB value");
+        }
+        if let Some(image_url) = &self.image_url {
+            ImageSource::url(image_url.as_str())?;
+        }
+        Ok(())
+    }
+
+    pub fn texts(&self, locale: &str) -> PanelTexts {
+        let defaults = default_texts(self.locale.as_deref().unwrap_or(locale));
+        let text = |custom: &Option<String>, default: &str| {
+            custom.clone().unwrap_or_else(|| default.to_string())
+        };
+        PanelTexts {
+            title: text(&self.title, defaults.title),
+            description: text(&self.description, defaults.description),
+            button_label: text(&self.button_label, defaults.button_label),
+            reply_message: text(&self.reply_message, defaults.reply_message),
+            link_label: text(&self.link_label, defaults.link_label),
+            success_message: text(&self.success_message, defaults.success_message),
+            failure_message: text(&self.failure_message, defaults.failure_message),
+            cooldown_message: text(&self.cooldown_message, defaults.cooldown_message),
+        }
+    }
+
+    /// Builds the embed and button posted in the panel channel.
+    pub fn build_message(
+        &self,
+        guild_locale: &str,
+        custom_id: String,
+    ) -> anyhow::Result<(Embed, Vec<Component>)> {
+        let texts = self.texts(guild_locale);
+        let mut embed = EmbedBuilder::new()
+            .title(texts.title)
+            .description(texts.description);
+        if let Some(color) = self.color {
+            embed = embed.color(color);
+        }
+        if let Some(image_url) = &self.image_url {
+            embed = embed.image(ImageSource::url(image_url.as_str())?);
+        }
+        let components = ComponentsBuilder::new()
+            .buttons(vec![ButtonBuilder::with_custom_id(
+                custom_id,
+                texts.button_label,
+                self.button_style.into(),
+            )
+            .build()])
+            .build();
+        Ok((embed.build(), components))
+    }
+}
+
+/// Whether a Discord request failed because the resource doesn't exist.
+pub fn is_not_found(error: &HttpError) -> bool {
+    matches!(error.kind(), ErrorType::Response { status, .. } if status.get() == 404)
+}
+
+async fn delete_message(state: &AppState, panel_id: i64, channel_id: i64, message_id: i64) {
+    if let Err(error) = state
+        .http
+        .delete_message(Id::new(channel_id as u64), Id::new(message_id as u64))
+        .await
+    {
+        if !is_not_found(&error) {
+            tracing::warn!("Failed to delete message of panel {}: {}", panel_id, error);
+        }
+    }
+}
+
+/// Deletes the posted message of a panel, or the whole forum post holding it.
+async fn delete_posted(state: &AppState, panel: &Panel, channel_id: i64, message_id: i64) {
+    let Some(thread_id) = panel.message_thread_id else {
+        return delete_message(state, panel.id, channel_id, message_id).await;
+    };
+    if let Err(error) = state.http.delete_channel(Id::new(thread_id as u64)).await {
+        if !is_not_found(&error) {
+            tracing::warn!(
+                "Failed to delete forum post of panel {}: {}",
+                panel.id,
+                error
+            );
+        }
+    }
+}
+
+/// Deletes the posted message of a panel, if any. Failures are only logged.
+pub async fn unpublish(state: &AppState, panel: &Panel) {
+    if let Some((channel_id, message_id)) = panel.message {
+        delete_posted(state, panel, channel_id, message_id).await;
+    }
+}
+
+/// Posts the panel in its channel, or edits the previously posted message in place.
+/// In a forum channel the panel is posted as a new forum post, pinned if the bot may.
+/// A message left in another channel is deleted and a deleted one is recreated.
+/// `repost` always replaces the old message with a new one.
+pub async fn publish(state: &AppState, panel: &Panel, repost: bool) -> anyhow::Result<()> {
+    let guild_id = panel.guild_id as u64;
+    let channel_id = panel.settings.channel_id as u64;
+    let preferred_locale = match state.cache.preferred_locale(Id::new(guild_id)) {
+        Some(locale) => locale,
+        None => {
+            state
+                .http
+                .guild(Id::new(guild_id))
+                .await?
+                .model()
+                .await?
+                .preferred_locale
+        }
+    };
+    let content = PanelContent::from_panel(panel)?;
+    let (embed, components) = content.build_message(&preferred_locale, custom_id(panel.id))?;
+    let embeds = [embed];
+
+    if let Some((posted_channel_id, message_id)) = panel.message {
+        if posted_channel_id == channel_id as i64 && !repost {
+            let message_channel_id = panel.message_thread_id.unwrap_or(posted_channel_id) as u64;
+            let result = state
+                .http
+                .update_message(Id::new(message_channel_id), Id::new(message_id as u64))
+                .embeds(Some(&embeds))?
+                .components(Some(&components))?
+                .await;
+            match result {
+                Ok(_) => return Ok(()),
+                Err(error) if is_not_found(&error) => {}
+                Err(error) => return Err(error.into()),
+            }
+        } else {
+            delete_posted(state, panel, posted_channel_id, message_id).await;
+        }
+    }
+
+    let channel = state
+        .http
+        .channel(Id::new(channel_id))
+        .await?
+        .model()
+        .await?;
+    let (message_id, thread_id) = if channel.kind == ChannelType::GuildForum {
+        let title = content.texts(&preferred_locale).title;
+        let title = title
+            .chars()
+            .take(MAX_FORUM_POST_TITLE_LENGTH)
+            .collect::<String>();
+        let post = state
+            .http
+            .create_forum_thread(channel.id, &title)
+            .message()
+            .embeds(&embeds)?
+            .components(&components)?
+            .await?
+            .model()
+            .await?;
+        if let Err(error) = state
+            .http
+            .update_channel(post.channel.id)
+            .flags(ChannelFlags::PINNED)
+            .await
+        {
+            // Manage Threads is only requested on invite when asked for, so a
+            // missing permission just leaves the post unpinned.
+            if !matches!(error.kind(), ErrorType::Response { status, .. } if status.get() == 403) {
+                tracing::warn!("Failed to pin forum post of panel {}: {}", panel.id, error);
+            }
+        }
+        (post.message.id, Some(post.channel.id.get() as i64))
+    } else {
+        let message = state
+            .http
+            .create_message(channel.id)
+            .embeds(&embeds)?
+            .components(&components)?
+            .await?
+            .model()
+            .await?;
+        (message.id, None)
+    };
+    db::set_panel_message(
+        &state.pool,
+        panel.id,
+        channel_id as i64,
+        message_id.get() as i64,
+        thread_id,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::email;
+
+const MAX_PATTERN_LENGTH: usize = 1024;
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+/// Compiles a guild's `email_pattern`, rejecting patterns that are too long or too
+/// expensive to compile.
+pub fn compile(pattern: &str) -> anyhow::Result<Regex> {
+    if pattern.len() > MAX_PATTERN_LENGTH {
+        anyhow::bail!(
+            "Pattern is too long (max {} characters)",
+            MAX_PATTERN_LENGTH
+        );
+    }
+    Ok(RegexBuilder::new(pattern)
+        .size_limit(MAX_COMPILED_SIZE)
+        .dfa_size_limit(MAX_COMPILED_SIZE)
+        .build()?)
+}
+
+/// Compiled patterns keyed by panel, invalidated when the settings version changes.
+#[derive(Default)]
+pub struct PatternCache {
+    patterns: Mutex<HashMap<i64, (i64, Regex)>>,
+}
+
+impl PatternCache {
+    pub fn get(&self, panel_id: i64, version: i64, pattern: &str) -> anyhow::Result<Regex> {
+        if let Some((cached_version, regex)) = self.patterns.lock().unwrap().get(&panel_id) {
+            if *cached_version == version {
+                return Ok(regex.clone());
+            }
+        }
+        let regex = compile(pattern)?;
+        self.patterns
+            .lock()
+            .unwrap()
+            .insert(panel_id, (version, regex.clone()));
+        Ok(regex)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalPartCharset {
+    #[default]
+    Any,
+    Digits,
+    Alphanumeric,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LocalPartRules {
+    pub prefix: String,
+    pub suffix: String,
+    /// Characters allowed between the prefix and the suffix.
+    pub charset: LocalPartCharset,
+    /// Bounds on the whole local part, prefix and suffix included.
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+}
+
+/// A structured alternative to a raw `email_pattern` for admins who don't write regex.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PatternForm {
+    pub allowed_domains: Vec<String>,
+    pub include_subdomains: bool,
+    pub local_part: LocalPartRules,
+}
+
+impl PatternForm {
+    /// Builds the regex stored as the guild's `email_pattern`.
+    pub fn to_regex(&self) -> anyhow::Result<String> {
+        if self.allowed_domains.is_empty() {
+            anyhow::bail!("At least one domain is required");
+        }
+        let domains = self
+            .allowed_domains
+            .iter()
+            .map(|domain| {
+                let domain = domain.trim().trim_start_matches('@');
+                Ok(regex::escape(&email::normalize_domain(domain)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let subdomains = if self.include_subdomains {
+            r"(?:[a-z0-9-]+\.)*"
+        } else {
+            ""
+        };
+
+        let local = &self.local_part;
+        let fixed = local.prefix.chars().count() + local.suffix.chars().count();
+        let min = match local.min_length {
+            Some(min_length) => min_length.saturating_sub(fixed),
+            None if fixed == 0 => 1,
+            None => 0,
+        };
+        let quantifier = match local.max_length {
+            Some(max_length) if max_length < fixed.max(1) => {
+                anyhow::bail!("Maximum length is shorter than the prefix and suffix")
+            }
+            Some(max_length) if max_length - fixed < min => {
+                anyhow::bail!("Maximum length is shorter than the minimum length")
+            }
+            Some(max_length) => format!("{{{},{}}}", min, max_length - fixed),
+            None => format!("{{{},}}", min),
+        };
+        let charset = match local.charset {
+            LocalPartCharset::Any => "[^@]",
+            LocalPartCharset::Digits => "[0-9]",
+            LocalPartCharset::Alphanumeric => "[a-z0-9]",
+        };
+
+        let pattern = format!(
+            "(?i)^{}{}{}{}@{}(?:{})$",
+            regex::escape(&local.prefix),
+            charset,
+            quantifier,
+            regex::escape(&local.suffix),
+            subdomains,
+            domains.join("|"),
+        );
+        compile(&pattern)?;
+        Ok(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(domains: &[&str], local_part: LocalPartRules) -> PatternForm {
+        PatternForm {
+            allowed_domains: domains.iter().map(|domain| domain.to_string()).collect(),
+            include_subdomains: false,
+            local_part,
+        }
+    }
+
+    fn matcher(form: &PatternForm) -> Regex {
+        compile(&form.to_regex().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn to_regex_matches_exact_domains() {
+        let regex = matcher(&form(
+            &["example.jp", "@Other.ac.jp"],
+            LocalPartRules::default(),
+        ));
+        assert!(regex.is_match("taro@example.jp"));
+        assert!(regex.is_match("taro@other.ac.jp"));
+        assert!(!regex.is_match("@example.jp"));
+        assert!(!regex.is_match("taro@sub.example.jp"));
+        assert!(!regex.is_match("taro@exampleXjp"));
+        assert!(!regex.is_match("taro@example.jp.evil"));
+    }
+
+    #[test]
+    fn to_regex_includes_subdomains() {
+        let mut form = form(&["example.jp"], LocalPartRules::default());
+        form.include_subdomains = true;
+        let regex = matcher(&form);
+        assert!(regex.is_match("taro@example.jp"));
+        assert!(regex.is_match("taro@mail.cs.example.jp"));
+        assert!(!regex.is_match("taro@badexample.jp"));
+    }
+
+    #[test]
+    fn to_regex_uses_punycode_for_idn_domains() {
+        let regex = matcher(&form(&["例え.jp"], LocalPartRules::default()));
+        assert!(regex.is_match("taro@xn--r8jz45g.jp"));
+    }
+
+    #[test]
+    fn to_regex_applies_prefix_suffix_and_lengths() {
+        let regex = matcher(&form(
+            &["example.jp"],
+            LocalPartRules {
+                prefix: "s".to_string(),
+                suffix: "x".to_string(),
+                charset: LocalPartCharset::Digits,
+                min_length: Some(4),
+                max_length: Some(6),
+            },
+        ));
+        assert!(regex.is_match("s12x@example.jp"));
+        assert!(regex.is_match("s1234x@example.jp"));
+        assert!(!regex.is_match("s1x@example.jp"));
+        assert!(!regex.is_match("s12345x@example.jp"));
+        assert!(!regex.is_match("sabx@example.jp"));
+        assert!(!regex.is_match("t12x@example.jp"));
+    }
+
+    #[test]
+    fn to_regex_allows_bare_prefix_without_min_length() {
+        let regex = matcher(&form(
+            &["example.jp"],
+            LocalPartRules {
+                prefix: "admin".to_string(),
+                ..LocalPartRules::default()
+            },
+        ));
+        assert!(regex.is_match("admin@example.jp"));
+        assert!(regex.is_match("admin2@example.jp"));
+        assert!(!regex.is_match("user@example.jp"));
+    }
+
+    #[test]
+    fn to_regex_escapes_prefix_and_suffix() {
+        let regex = matcher(&form(
+            &["example.jp"],
+            LocalPartRules {
+                prefix: "a.b+".to_string(),
+                ..LocalPartRules::default()
+            },
+        ));
+        assert!(regex.is_match("a.b+1@example.jp"));
+        assert!(!regex.is_match("axbb1@example.jp"));
+    }
+
+    #[test]
+    fn to_regex_rejects_invalid_forms() {
+        assert!(form(&[], LocalPartRules::default()).to_regex().is_err());
+        assert!(form(&["exa mple.jp"], LocalPartRules::default())
+            .to_regex()
+            .is_err());
+        let too_short = LocalPartRules {
+            prefix: "abc".to_string(),
+            max_length: Some(2),
+            ..LocalPartRules::default()
+        };
+        assert!(form(&["example.jp"], too_short).to_regex().is_err());
+        let max_below_min = LocalPartRules {
+            min_length: Some(5),
+            max_length: Some(3),
+            ..LocalPartRules::default()
+        };
+        assert!(form(&["example.jp"], max_below_min).to_regex().is_err());
+    }
+}
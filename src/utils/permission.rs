@@ -0,0 +1,384 @@
+use crate::utils::panel;
+use crate::utils::state::AppState;
+
+use std::cmp::Reverse;
+
+use serde::Serialize;
+use twilight_model::channel::permission_overwrite::PermissionOverwrite;
+use twilight_model::channel::{Channel, ChannelType};
+use twilight_model::guild::{Permissions, Role};
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker};
+use twilight_model::id::Id;
+use twilight_util::permission_calculator::PermissionCalculator;
+
+/// Whether a panel can be posted in channels of this type.
+pub fn is_panel_channel(kind: ChannelType) -> bool {
+    matches!(
+        kind,
+        ChannelType::GuildText
+            | ChannelType::GuildAnnouncement
+            | ChannelType::GuildForum
+            | ChannelType::AnnouncementThread
+            | ChannelType::PublicThread
+            | ChannelType::PrivateThread
+    )
+}
+
+/// Whether admin notifications can be sent to channels of this type.
+pub fn is_log_channel(kind: ChannelType) -> bool {
+    matches!(
+        kind,
+        ChannelType::GuildText | ChannelType::GuildAnnouncement
+    )
+}
+
+/// Permissions the bot needs to post the panel in a channel of this type.
+pub fn required_permissions(kind: ChannelType) -> Permissions {
+    let send = if kind.is_thread() {
+        Permissions::SEND_MESSAGES_IN_THREADS
+    } else {
+        Permissions::SEND_MESSAGES
+    };
+    Permissions::VIEW_CHANNEL | send | Permissions::EMBED_LINKS
+}
+
+/// Permissions requested when inviting the bot, with why each one is needed.
+pub const INVITE_PERMISSIONS: &[(Permissions, &str, &str)] = &[
+    (
+        Permissions::VIEW_CHANNEL,
+        "View Channels",
+        "See the channels panels are posted in",
+    ),
+    (
+        Permissions::SEND_MESSAGES,
+        "Send Messages",
+        "Post panels in channels and create panel posts in forums",
+    ),
+    (
+        Permissions::SEND_MESSAGES_IN_THREADS,
+        "Send Messages in Threads",
+        "Post panels in threads",
+    ),
+    (
+        Permissions::EMBED_LINKS,
+        "Embed Links",
+        "Show the panel as an embed",
+    ),
+    (
+        Permissions::MANAGE_ROLES,
+        "Manage Roles",
+        "Grant the verified role and tag roles",
+    ),
+];
+
+/// Requested only when the inviter wants panel posts in forums pinned, since it
+/// also lets the bot delete and lock every thread of the guild.
+pub const FORUM_PIN_PERMISSION: (Permissions, &str, &str) = (
+    Permissions::MANAGE_THREADS,
+    "Manage Threads",
+    "Pin panel posts in forums",
+);
+
+/// Permissions to request on invite, [`FORUM_PIN_PERMISSION`] included only
+/// when `forum_pin` is set.
+pub fn invite_permissions(forum_pin: bool) -> Vec<(Permissions, &'static str, &'static str)> {
+    let mut permissions = INVITE_PERMISSIONS.to_vec();
+    if forum_pin {
+        permissions.push(FORUM_PIN_PERMISSION);
+    }
+    permissions
+}
+
+/// Union of the permissions.
+pub fn permission_bits(permissions: &[(Permissions, &str, &str)]) -> Permissions {
+    permissions
+        .iter()
+        .fold(Permissions::empty(), |bits, (permission, _, _)| {
+            bits | *permission
+        })
+}
+
+/// Readable names of the permissions, e.g. "View Channels, Embed Links".
+pub fn permission_names(permissions: Permissions) -> String {
+    let known = invite_permissions(true);
+    let mut names: Vec<String> = known
+        .iter()
+        .filter(|(permission, _, _)| permissions.contains(*permission))
+        .map(|(_, name, _)| name.to_string())
+        .collect();
+    let others = permissions - permission_bits(&known);
+    if !others.is_empty() {
+        names.push(format!("{:?}", others));
+    }
+    names.join(", ")
+}
+
+/// Something that keeps the bot from posting a panel or granting a role.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    MissingManageRoles,
+    RoleNotFound {
+        role_id: String,
+    },
+    RoleNotAssignable {
+        role_id: String,
+    },
+    RoleAboveBot {
+        role_id: String,
+    },
+    ChannelNotFound {
+        channel_id: String,
+    },
+    UnsupportedChannel {
+        channel_id: String,
+        kind: String,
+    },
+    ThreadLocked {
+        channel_id: String,
+    },
+    MissingChannelPermissions {
+        channel_id: String,
+        permissions: String,
+    },
+}
+
+impl Problem {
+    /// What is wrong and how to fix it, for the dashboard.
+    pub fn message(&self) -> String {
+        match self {
+            Self::MissingManageRoles => {
+                "Give the bot the Manage Roles permission so it can grant roles".to_string()
+            }
+            Self::RoleNotFound { role_id } => format!("Role {} no longer exists", role_id),
+            Self::RoleNotAssignable { role_id } => {
+                format!(
+                    "Role {} is managed by Discord and can't be granted",
+                    role_id
+                )
+            }
+            Self::RoleAboveBot { role_id } => format!(
+                "Role {} is not below the bot's highest role, move the bot's role above it",
+                role_id
+            ),
+            Self::ChannelNotFound { channel_id } => format!(
+                "Channel {} doesn't exist or the bot can't see it",
+                channel_id
+            ),
+            Self::UnsupportedChannel { channel_id, kind } => {
+                format!("Channel {} of type {} can't be used here", channel_id, kind)
+            }
+            Self::ThreadLocked { channel_id } => format!("Thread {} is locked", channel_id),
+            Self::MissingChannelPermissions {
+                channel_id,
+                permissions,
+            } => format!("Give the bot {} in channel {}", permissions, channel_id),
+        }
+    }
+}
+
+/// Looks a channel up in the gateway cache, then over REST. `None` if it doesn't exist.
+async fn fetch_channel(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    channel_id: Id<ChannelMarker>,
+) -> anyhow::Result<Option<Channel>> {
+    if let Some(channel) = state.cache.channel(guild_id, channel_id) {
+        return Ok(Some(channel));
+    }
+    match state.http.channel(channel_id).await {
+        Ok(response) => Ok(Some(response.model().await?)),
+        Err(error) if panel::is_not_found(&error) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Position of a role in the hierarchy. Roles on the same position rank by age.
+fn hierarchy(role: &Role) -> (i64, Reverse<Id<RoleMarker>>) {
+    (role.position, Reverse(role.id))
+}
+
+/// The bot's roles and permissions in a guild, fetched once to run several checks.
+pub struct BotPermissions {
+    guild_id: Id<GuildMarker>,
+    owner_id: Id<UserMarker>,
+    roles: Vec<Role>,
+    user_id: Id<UserMarker>,
+    everyone: Permissions,
+    member_roles: Vec<(Id<RoleMarker>, Permissions)>,
+    highest_role: Option<(i64, Reverse<Id<RoleMarker>>)>,
+}
+
+impl BotPermissions {
+    pub async fn load(state: &AppState, guild_id: u64) -> anyhow::Result<Self> {
+        let guild_id = Id::new(guild_id);
+        let (owner_id, roles) = match (state.cache.owner_id(guild_id), state.cache.roles(guild_id))
+        {
+            (Some(owner_id), Some(roles)) => (owner_id, roles),
+            _ => {
+                let guild = state.http.guild(guild_id).await?.model().await?;
+                (guild.owner_id, guild.roles)
+            }
+        };
+        let bot_role_ids = match state.cache.member_roles(guild_id, state.bot_user_id) {
+            Some(role_ids) => role_ids,
+            None => {
+                state
+                    .http
+                    .guild_member(guild_id, state.bot_user_id)
+                    .await?
+                    .model()
+                    .await?
+                    .roles
+            }
+        };
+        let everyone = roles
+            .iter()
+            .find(|role| role.id.cast() == guild_id)
+            .map(|role| role.permissions)
+            .unwrap_or_else(Permissions::empty);
+        let bot_roles = roles
+            .iter()
+            .filter(|role| bot_role_ids.contains(&role.id))
+            .collect::<Vec<_>>();
+        let member_roles = bot_roles
+            .iter()
+            .map(|role| (role.id, role.permissions))
+            .collect();
+        let highest_role = bot_roles.iter().map(|role| hierarchy(role)).max();
+
+        Ok(Self {
+            guild_id,
+            owner_id,
+            roles,
+            user_id: state.bot_user_id,
+            everyone,
+            member_roles,
+            highest_role,
+        })
+    }
+
+    fn calculator(&self) -> PermissionCalculator<'_> {
+        PermissionCalculator::new(
+            self.guild_id,
+            self.user_id,
+            self.everyone,
+            &self.member_roles,
+        )
+        .owner_id(self.owner_id)
+    }
+
+    /// Checks that the bot may grant the role.
+    pub fn check_role(&self, role_id: i64) -> Option<Problem> {
+        if !self.calculator().root().contains(Permissions::MANAGE_ROLES) {
+            return Some(Problem::MissingManageRoles);
+        }
+        let role_id_str = role_id.to_string();
+        let Some(role) = self
+            .roles
+            .iter()
+            .find(|role| role.id.get() == role_id as u64)
+        else {
+            return Some(Problem::RoleNotFound {
+                role_id: role_id_str,
+            });
+        };
+        if role.managed || role.id.cast() == self.guild_id {
+            return Some(Problem::RoleNotAssignable {
+                role_id: role_id_str,
+            });
+        }
+        if self.owner_id != self.user_id
+            && self
+                .highest_role
+                .is_none_or(|highest| hierarchy(role) >= highest)
+        {
+            return Some(Problem::RoleAboveBot {
+                role_id: role_id_str,
+            });
+        }
+        None
+    }
+
+    /// Checks that the bot can post the panel in the channel.
+    pub async fn check_panel_channel(
+        &self,
+        state: &AppState,
+        channel_id: i64,
+    ) -> anyhow::Result<Option<Problem>> {
+        self.check_channel(state, channel_id, is_panel_channel, required_permissions)
+            .await
+    }
+
+    /// Checks that the bot can send admin notifications to the channel.
+    pub async fn check_log_channel(
+        &self,
+        state: &AppState,
+        channel_id: i64,
+    ) -> anyhow::Result<Option<Problem>> {
+        self.check_channel(state, channel_id, is_log_channel, |_| {
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES
+        })
+        .await
+    }
+
+    /// Checks that the channel is of a supported type and the bot has the required
+    /// permissions in it. Threads take the permission overwrites of their parent channel.
+    async fn check_channel(
+        &self,
+        state: &AppState,
+        channel_id: i64,
+        supported: fn(ChannelType) -> bool,
+        required: fn(ChannelType) -> Permissions,
+    ) -> anyhow::Result<Option<Problem>> {
+        let channel_id_str = channel_id.to_string();
+        let Some(channel_id) = Id::new_checked(channel_id as u64) else {
+            return Ok(Some(Problem::ChannelNotFound {
+                channel_id: channel_id_str,
+            }));
+        };
+        let Some(channel) = fetch_channel(state, self.guild_id, channel_id).await? else {
+            return Ok(Some(Problem::ChannelNotFound {
+                channel_id: channel_id_str,
+            }));
+        };
+        if channel.guild_id != Some(self.guild_id) {
+            return Ok(Some(Problem::ChannelNotFound {
+                channel_id: channel_id_str,
+            }));
+        }
+        if !supported(channel.kind) {
+            return Ok(Some(Problem::UnsupportedChannel {
+                channel_id: channel_id_str,
+                kind: channel.kind.name().to_string(),
+            }));
+        }
+        if channel
+            .thread_metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.locked)
+        {
+            return Ok(Some(Problem::ThreadLocked {
+                channel_id: channel_id_str,
+            }));
+        }
+
+        let overwrites: Vec<PermissionOverwrite> =
+            match (channel.kind.is_thread(), channel.parent_id) {
+                (true, Some(parent_id)) => fetch_channel(state, self.guild_id, parent_id)
+                    .await?
+                    .and_then(|parent| parent.permission_overwrites)
+                    .unwrap_or_default(),
+                _ => channel.permission_overwrites.unwrap_or_default(),
+            };
+        let permissions = self.calculator().in_channel(channel.kind, &overwrites);
+        let missing = required(channel.kind) - permissions;
+        if !missing.is_empty() {
+            return Ok(Some(Problem::MissingChannelPermissions {
+                channel_id: channel_id_str,
+                permissions: permission_names(missing),
+            }));
+        }
+        Ok(None)
+    }
+}
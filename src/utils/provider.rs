@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+
+use crate::utils::email;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("../../assets/disposable_domains.txt");
+
+const FREE_MAIL_DOMAINS: [&str; 36] = [
+    "126.com",
+    "163.com",
+    "aol.com",
+    "au.com",
+    "docomo.ne.jp",
+    "ezweb.ne.jp",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "gmx.net",
+    "googlemail.com",
+    "hotmail.co.jp",
+    "hotmail.com",
+    "i.softbank.jp",
+    "icloud.com",
+    "live.com",
+    "live.jp",
+    "mac.com",
+    "mail.com",
+    "mail.ru",
+    "me.com",
+    "msn.com",
+    "naver.com",
+    "outlook.com",
+    "outlook.jp",
+    "proton.me",
+    "protonmail.com",
+    "qq.com",
+    "softbank.ne.jp",
+    "web.de",
+    "yahoo.co.jp",
+    "yahoo.com",
+    "yandex.com",
+    "yandex.ru",
+    "ymail.com",
+    "zoho.com",
+];
+
+fn parse_domains(data: &str) -> HashSet<String> {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Local file with extra disposable domains, added to the bundled list.
+pub static DISPOSABLE_DOMAINS_PATH: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("DISPOSABLE_DOMAINS_PATH")
+        .ok()
+        .filter(|path| !path.is_empty())
+});
+
+fn matches_domain(domain: &str, contains: impl Fn(&str) -> bool) -> bool {
+    contains(domain)
+        || email::parent_domains(domain)
+            .iter()
+            .any(|parent| contains(parent))
+}
+
+/// The set of known disposable domains: the bundled list plus the domains in
+/// `DISPOSABLE_DOMAINS_PATH`, which can be reloaded at runtime.
+pub struct DisposableDomains {
+    domains: RwLock<HashSet<String>>,
+}
+
+impl DisposableDomains {
+    fn read() -> anyhow::Result<HashSet<String>> {
+        let mut domains = parse_domains(BUNDLED_DISPOSABLE_DOMAINS);
+        if let Some(path) = DISPOSABLE_DOMAINS_PATH.as_deref() {
+            domains.extend(parse_domains(&fs::read_to_string(path)?));
+        }
+        Ok(domains)
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let domains = Self::read()?;
+        tracing::info!("Load {} disposable domains", domains.len());
+        Ok(Self {
+            domains: RwLock::new(domains),
+        })
+    }
+
+    /// Re-reads the custom list and returns the new size.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let domains = Self::read()?;
+        let count = domains.len();
+        *self.domains.write().unwrap() = domains;
+        Ok(count)
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        let domains = self.domains.read().unwrap();
+        matches_domain(domain, |domain| domains.contains(domain))
+    }
+}
+
+/// Whether the domain, or a domain it's a subdomain of, is a free mail provider.
+pub fn is_free_mail(domain: &str) -> bool {
+    matches_domain(domain, |domain| FREE_MAIL_DOMAINS.contains(&domain))
+}
@@ -0,0 +1,95 @@
+use crate::utils::state::AppState;
+
+use bb8_redis::redis;
+
+/// A fixed window bucket: at most `max` hits per `window_secs` per subject.
+pub struct Limit {
+    pub name: &'static str,
+    pub max: u64,
+    pub window_secs: u64,
+}
+
+pub const VERIFY_PER_IP: Limit = Limit {
+    name: "verify:ip",
+    max: 20,
+    window_secs: 60,
+};
+pub const VERIFY_PER_USER: Limit = Limit {
+    name: "verify:user",
+    max: 10,
+    window_secs: 60 * 10,
+};
+pub const VERIFY_PER_GUILD: Limit = Limit {
+    name: "verify:guild",
+    max: 300,
+    window_secs: 60,
+};
+pub const EXCHANGE_TOKEN_PER_IP: Limit = Limit {
+    name: "exchange_token:ip",
+    max: 20,
+    window_secs: 60,
+};
+pub const EXCHANGE_TOKEN_PER_USER: Limit = Limit {
+    name: "exchange_token:user",
+    max: 10,
+    window_secs: 60 * 10,
+};
+pub const BUTTON_PER_USER: Limit = Limit {
+    name: "button:user",
+    max: 5,
+    window_secs: 60,
+};
+pub const BUTTON_PER_GUILD: Limit = Limit {
+    name: "button:guild",
+    max: 300,
+    window_secs: 60,
+};
+
+/// Counts a hit and returns the seconds left in the window, rounded up, if
+/// it's over the limit, or -1 if the hit is allowed. KEYS: bucket. ARGV:
+/// window (s), max.
+const HIT_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+if count <= tonumber(ARGV[2]) then
+    return -1
+end
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then
+    -- A key without expiry shouldn't exist, but don't leave the client waiting forever.
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+    return tonumber(ARGV[1])
+end
+return math.max(1, math.ceil(ttl / 1000))
+";
+
+/// Counts a hit of `subject` (an IP, user or guild ID) against the limit.
+/// Returns the seconds to wait if it's exceeded.
+pub async fn hit(state: &AppState, limit: &Limit, subject: &str) -> anyhow::Result<Option<u64>> {
+    let mut conn = state.redis.get().await?;
+    let retry_after: i64 = redis::cmd("EVAL")
+        .arg(HIT_SCRIPT)
+        .arg(1)
+        .arg(format!("ratelimit:{}:{}", limit.name, subject))
+        .arg(limit.window_secs)
+        .arg(limit.max)
+        .query_async(&mut *conn)
+        .await?;
+    Ok(match retry_after {
+        retry_after if retry_after < 0 => None,
+        retry_after => Some(retry_after as u64),
+    })
+}
+
+/// Counts a hit against each bucket and returns the longest wait, if any.
+pub async fn hit_all(state: &AppState, limits: &[(&Limit, String)]) -> anyhow::Result<Option<u64>> {
+    let mut wait = None;
+    for (limit, subject) in limits {
+        if let Some(retry_after) = hit(state, limit, subject).await? {
+            wait = Some(wait.unwrap_or(0).max(retry_after));
+        }
+    }
+    Ok(wait)
+}
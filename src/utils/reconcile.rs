@@ -0,0 +1,324 @@
+use crate::db::panel as panel_db;
+use crate::db::verification as verification_db;
+use crate::utils::lock::{self, Lock};
+use crate::utils::roles;
+use crate::utils::rules::{Rules, Verdict};
+use crate::utils::state::AppState;
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bb8_redis::redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use twilight_http::request::AuditLogReason;
+use twilight_model::id::Id;
+
+/// Pause between role changes, keeps a large run well below the rate limits.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+const PROGRESS_TTL_SECS: u64 = 60 * 60 * 24;
+/// How long a run holds the guild without renewing, long enough to plan a large
+/// guild. A crashed run stops blocking new ones after this.
+const RUN_LOCK_SECS: u64 = 60 * 5;
+const REASON: &str = "Verification rules changed";
+
+/// Roles to add to and remove from a member to match the current rules.
+pub struct MemberChange {
+    pub user_id: i64,
+    pub add: Vec<i64>,
+    pub remove: Vec<i64>,
+}
+
+/// A verification whose granted roles change.
+struct VerificationUpdate {
+    user_id: i64,
+    panel_id: i64,
+    granted: Vec<i64>,
+    expected: Vec<i64>,
+}
+
+impl VerificationUpdate {
+    /// The roles to store once the run is done: a role whose change failed stays
+    /// as it was granted.
+    fn role_ids(&self, failed: &HashSet<(i64, i64)>) -> Vec<i64> {
+        let failed = |role_id: &i64| failed.contains(&(self.user_id, *role_id));
+        let mut role_ids: Vec<i64> = self
+            .expected
+            .iter()
+            .filter(|role_id| !failed(role_id) || self.granted.contains(role_id))
+            .copied()
+            .collect();
+        role_ids.extend(
+            self.granted
+                .iter()
+                .filter(|role_id| failed(role_id) && !self.expected.contains(role_id)),
+        );
+        role_ids
+    }
+}
+
+pub struct Plan {
+    pub changes: Vec<MemberChange>,
+    updates: Vec<VerificationUpdate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconcileStatus {
+    Running,
+    Done,
+    /// The run stopped without finishing, e.g. its instance crashed.
+    Interrupted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Progress {
+    pub status: ReconcileStatus,
+    pub total: usize,
+    pub processed: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Self {
+            status: ReconcileStatus::Running,
+            total,
+            processed: 0,
+            added: 0,
+            removed: 0,
+            failed: 0,
+        }
+    }
+}
+
+fn progress_key(guild_id: i64) -> String {
+    format!("reconcile:{}", guild_id)
+}
+
+fn run_lock_name(guild_id: i64) -> String {
+    format!("reconcile:{}", guild_id)
+}
+
+pub async fn get_progress(state: &AppState, guild_id: i64) -> anyhow::Result<Option<Progress>> {
+    let data: Option<String> = {
+        let mut conn = state.redis.get().await?;
+        conn.get(progress_key(guild_id)).await?
+    };
+    let Some(data) = data else {
+        return Ok(None);
+    };
+    let mut progress: Progress = serde_json::from_str(&data)?;
+    if progress.status == ReconcileStatus::Running
+        && !lock::is_locked(state, &run_lock_name(guild_id)).await?
+    {
+        progress.status = ReconcileStatus::Interrupted;
+    }
+    Ok(Some(progress))
+}
+
+async fn set_progress(state: &AppState, guild_id: i64, progress: &Progress) -> anyhow::Result<()> {
+    let mut conn = state.redis.get().await?;
+    conn.set_ex::<_, _, ()>(
+        progress_key(guild_id),
+        serde_json::to_string(progress)?,
+        PROGRESS_TTL_SECS,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Evaluates every stored verification of the members in the guild against the
+/// current rules. Verifications of broken or deleted panels are left alone.
+pub async fn plan(state: &AppState, guild_id: i64) -> anyhow::Result<Plan> {
+    let mut rules = HashMap::new();
+    let mut members = BTreeMap::<i64, (BTreeSet<i64>, BTreeSet<i64>)>::new();
+    let mut updates = Vec::new();
+    for verification in verification_db::get_guild_verifications(&state.pool, guild_id).await? {
+        if let Entry::Vacant(entry) = rules.entry(verification.panel_id) {
+            let panel = panel_db::get_panel(&state.pool, guild_id, verification.panel_id)
+                .await?
+                .filter(|panel| panel.broken_reason.is_none());
+            let panel_rules = match panel {
+                Some(panel) => Some((Rules::load(state, &panel).await?, panel)),
+                None => None,
+            };
+            entry.insert(panel_rules);
+        }
+        let (member_granted, member_expected) = members.entry(verification.user_id).or_default();
+        member_granted.extend(&verification.role_ids);
+        let Some((panel_rules, panel)) = &rules[&verification.panel_id] else {
+            member_expected.extend(&verification.role_ids);
+            continue;
+        };
+
+        let role_ids = match panel_rules.evaluate(state, &verification.email).await? {
+            Verdict::Accepted { tags, .. } => roles::verified_role_ids(state, panel, &tags).await?,
+            _ => Vec::new(),
+        };
+        member_expected.extend(&role_ids);
+        if role_ids != verification.role_ids {
+            updates.push(VerificationUpdate {
+                user_id: verification.user_id,
+                panel_id: verification.panel_id,
+                granted: verification.role_ids,
+                expected: role_ids,
+            });
+        }
+    }
+
+    let changes = members
+        .into_iter()
+        .map(|(user_id, (granted, expected))| MemberChange {
+            user_id,
+            add: expected.difference(&granted).copied().collect(),
+            remove: granted.difference(&expected).copied().collect(),
+        })
+        .filter(|change| !change.add.is_empty() || !change.remove.is_empty())
+        .collect();
+
+    Ok(Plan { changes, updates })
+}
+
+async fn change_role(
+    state: &AppState,
+    guild_id: i64,
+    user_id: i64,
+    role_id: i64,
+    add: bool,
+) -> anyhow::Result<()> {
+    let (guild_id, user_id, role_id) = (
+        Id::new(guild_id as u64),
+        Id::new(user_id as u64),
+        Id::new(role_id as u64),
+    );
+    if add {
+        state
+            .http
+            .add_guild_member_role(guild_id, user_id, role_id)
+            .reason(REASON)?
+            .await?;
+    } else {
+        state
+            .http
+            .remove_guild_member_role(guild_id, user_id, role_id)
+            .reason(REASON)?
+            .await?;
+    }
+    Ok(())
+}
+
+/// Claims the guild's run so a second one isn't started alongside it. Returns
+/// `None` if a run is in progress.
+pub async fn claim(state: &AppState, guild_id: i64) -> anyhow::Result<Option<Lock>> {
+    lock::try_lock(state, &run_lock_name(guild_id), RUN_LOCK_SECS).await
+}
+
+/// Applies a plan one role change at a time, recording progress in Redis, then
+/// stores the roles of the changes that succeeded. Renews the claim after each
+/// change and stops if it was lost.
+pub async fn apply(state: Arc<AppState>, guild_id: i64, plan: Plan, claim: Lock) {
+    let mut progress = Progress::new(plan.changes.len());
+    if let Err(error) = set_progress(&state, guild_id, &progress).await {
+        tracing::warn!(
+            "Failed to save reconcile progress of {}: {}",
+            guild_id,
+            error
+        );
+    }
+    let mut failed = HashSet::new();
+    'changes: for change in &plan.changes {
+        let role_changes = change
+            .add
+            .iter()
+            .map(|role_id| (*role_id, true))
+            .chain(change.remove.iter().map(|role_id| (*role_id, false)));
+        for (role_id, add) in role_changes {
+            match change_role(&state, guild_id, change.user_id, role_id, add).await {
+                Ok(()) if add => progress.added += 1,
+                Ok(()) => progress.removed += 1,
+                Err(error) => {
+                    tracing::warn!(
+                        "Failed to reconcile role {} of {} in {}: {}",
+                        role_id,
+                        change.user_id,
+                        guild_id,
+                        error
+                    );
+                    progress.failed += 1;
+                    failed.insert((change.user_id, role_id));
+                }
+            }
+            tokio::time::sleep(REQUEST_INTERVAL).await;
+            match lock::renew(&state, &claim, RUN_LOCK_SECS).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::warn!("Reconciliation of {} lost its claim, stopping", guild_id);
+                    break 'changes;
+                }
+                Err(error) => {
+                    tracing::warn!("Failed to renew reconciliation of {}: {}", guild_id, error)
+                }
+            }
+        }
+        progress.processed += 1;
+        if let Err(error) = set_progress(&state, guild_id, &progress).await {
+            tracing::warn!(
+                "Failed to save reconcile progress of {}: {}",
+                guild_id,
+                error
+            );
+        }
+    }
+
+    // Members the run didn't get to keep their stored roles.
+    let processed: HashSet<i64> = plan
+        .changes
+        .iter()
+        .take(progress.processed)
+        .map(|change| change.user_id)
+        .collect();
+    let changed: HashSet<i64> = plan.changes.iter().map(|change| change.user_id).collect();
+    for update in &plan.updates {
+        if changed.contains(&update.user_id) && !processed.contains(&update.user_id) {
+            continue;
+        }
+        if let Err(error) = verification_db::set_verification_roles(
+            &state.pool,
+            guild_id,
+            update.user_id,
+            update.panel_id,
+            &update.role_ids(&failed),
+        )
+        .await
+        {
+            tracing::warn!(
+                "Failed to update verification of {}: {}",
+                update.user_id,
+                error
+            );
+        }
+    }
+    progress.status = if progress.processed == progress.total {
+        ReconcileStatus::Done
+    } else {
+        ReconcileStatus::Interrupted
+    };
+    if let Err(error) = set_progress(&state, guild_id, &progress).await {
+        tracing::warn!(
+            "Failed to save reconcile progress of {}: {}",
+            guild_id,
+            error
+        );
+    }
+    if let Err(error) = lock::release(&state, claim).await {
+        tracing::warn!(
+            "Failed to release reconciliation of {}: {}",
+            guild_id,
+            error
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::db::verify as verify_db;
+use crate::utils::lock;
+use crate::utils::state::AppState;
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+/// Days the data of a guild is kept after the bot is removed from it.
+static GUILD_RETENTION_DAYS: Lazy<i32> = Lazy::new(|| {
+    env::var("GUILD_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.trim().parse().ok())
+        .unwrap_or(30)
+});
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Held a bit less than the interval, so the next purge can take it again.
+const PURGE_LOCK_SECS: u64 = 60 * 55;
+/// Instances started together reconcile once.
+const RECONCILE_LOCK_SECS: u64 = 60 * 10;
+const GUILDS_PAGE_LIMIT: u16 = 200;
+
+/// Marks guilds the bot was removed from while it was offline.
+async fn reconcile_guilds(state: &AppState) -> anyhow::Result<()> {
+    let mut guild_ids = Vec::new();
+    let mut after = None;
+    loop {
+        let mut request = state.http.current_user_guilds().limit(GUILDS_PAGE_LIMIT)?;
+        if let Some(after) = after {
+            request = request.after(after);
+        }
+        let guilds = request.await?.model().await?;
+        after = guilds.last().map(|guild| guild.id);
+        guild_ids.extend(guilds.iter().map(|guild| guild.id.get() as i64));
+        if guilds.len() < GUILDS_PAGE_LIMIT as usize {
+            break;
+        }
+    }
+    let left = verify_db::reconcile_guilds(&state.pool, &guild_ids).await?;
+    tracing::info!("Reconcile {} guilds, {} left", guild_ids.len(), left);
+    Ok(())
+}
+
+/// Reconciles the stored guilds on startup, then purges the data of guilds left
+/// longer than `GUILD_RETENTION_DAYS` ago every hour.
+/// Only one instance reconciles and purges at a time.
+pub async fn run_retention(state: Arc<AppState>) {
+    match lock::try_lock(&state, "retention:reconcile", RECONCILE_LOCK_SECS).await {
+        Ok(Some(_)) => {
+            if let Err(error) = reconcile_guilds(&state).await {
+                tracing::warn!("Failed to reconcile guilds: {}", error);
+            }
+        }
+        Ok(None) => {}
+        Err(error) => tracing::warn!("Failed to lock guild reconciliation: {}", error),
+    }
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match lock::try_lock(&state, "retention:purge", PURGE_LOCK_SECS).await {
+            Ok(Some(_)) => {}
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::warn!("Failed to lock guild purge: {}", error);
+                continue;
+            }
+        }
+        match verify_db::purge_left_guilds(&state.pool, *GUILD_RETENTION_DAYS).await {
+            Ok(guild_ids) if !guild_ids.is_empty() => {
+                tracing::info!("Purge data of left guilds: {:?}", guild_ids)
+            }
+            Ok(_) => {}
+            Err(error) => tracing::warn!("Failed to purge left guilds: {}", error),
+        }
+    }
+}
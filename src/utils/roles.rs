@@ -0,0 +1,48 @@
+use crate::db::panel::Panel;
+use crate::db::tag_role as tag_db;
+use crate::utils::jobs::{self, Job};
+use crate::utils::state::AppState;
+
+/// Roles a member verified through the panel gets: the panel's role and the
+/// roles of the tags their address matched.
+pub async fn verified_role_ids(
+    state: &AppState,
+    panel: &Panel,
+    tags: &[String],
+) -> anyhow::Result<Vec<i64>> {
+    let mut role_ids = vec![panel.settings.role_id];
+    if !tags.is_empty() {
+        for tag_role_id in tag_db::get_roles_by_tags(&state.pool, panel.id, tags).await? {
+            if !role_ids.contains(&tag_role_id) {
+                role_ids.push(tag_role_id);
+            }
+        }
+    }
+    Ok(role_ids)
+}
+
+/// Enqueues a job granting each role. `idempotency_key` identifies the action,
+/// e.g. one verification, and is suffixed with the role ID.
+pub async fn grant_roles(
+    state: &AppState,
+    guild_id: i64,
+    user_id: i64,
+    role_ids: &[i64],
+    reason: &str,
+    idempotency_key: &str,
+) -> anyhow::Result<()> {
+    for &role_id in role_ids {
+        jobs::enqueue(
+            state,
+            &Job::AddRole {
+                guild_id,
+                user_id,
+                role_id,
+                reason: reason.to_string(),
+            },
+            Some(&format!("{}:{}", idempotency_key, role_id)),
+        )
+        .await?;
+    }
+    Ok(())
+}
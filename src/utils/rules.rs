@@ -0,0 +1,114 @@
+use crate::db::deny_address as deny_db;
+use crate::db::mail_address as mail_db;
+use crate::db::panel::Panel;
+use crate::utils::email::{self, NormalizeOptions};
+use crate::utils::provider;
+use crate::utils::state::AppState;
+
+use regex::Regex;
+use serde::Serialize;
+
+/// Outcome of running an address through a panel's rules, in evaluation order.
+#[derive(Serialize, Debug)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Verdict {
+    Invalid,
+    Denied {
+        entry: String,
+    },
+    Disposable,
+    FreeMail,
+    PatternMismatch,
+    NotInList,
+    Accepted {
+        /// The address under the panel's normalization.
+        normalized_email: String,
+        entries: Vec<String>,
+        tags: Vec<String>,
+    },
+}
+
+impl Verdict {
+    /// Short English reason used in API errors and logs.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Invalid => "Invalid email",
+            Self::Denied { .. } => "Mail is denied",
+            Self::Disposable => "Disposable mail is not allowed",
+            Self::FreeMail => "Free mail is not allowed",
+            Self::PatternMismatch => "Mail is not match",
+            Self::NotInList => "Mail is not inside at list",
+            Self::Accepted { .. } => "Accepted",
+        }
+    }
+}
+
+/// Everything needed to decide whether an address may verify through a panel.
+pub struct Rules {
+    pub panel_id: i64,
+    pub pattern: Regex,
+    pub enable_check_mail: bool,
+    pub normalize: NormalizeOptions,
+    pub reject_disposable: bool,
+    pub reject_free_mail: bool,
+}
+
+impl Rules {
+    pub async fn load(state: &AppState, panel: &Panel) -> anyhow::Result<Self> {
+        let pattern = state.patterns.get(
+            panel.id,
+            panel.settings_version,
+            &panel.settings.email_pattern,
+        )?;
+
+        Ok(Self {
+            panel_id: panel.id,
+            pattern,
+            enable_check_mail: panel.settings.enable_check_mail,
+            normalize: panel.settings.normalize,
+            reject_disposable: panel.settings.reject_disposable,
+            reject_free_mail: panel.settings.reject_free_mail,
+        })
+    }
+
+    pub async fn evaluate(&self, state: &AppState, email: &str) -> anyhow::Result<Verdict> {
+        let Ok(email) = email::normalize(email, &self.normalize) else {
+            return Ok(Verdict::Invalid);
+        };
+        if let Some(entry) = deny_db::find_denied(&state.pool, self.panel_id, &email).await? {
+            return Ok(Verdict::Denied { entry });
+        }
+        let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+        if self.reject_disposable && state.disposable_domains.contains(domain) {
+            return Ok(Verdict::Disposable);
+        }
+        if self.reject_free_mail && provider::is_free_mail(domain) {
+            return Ok(Verdict::FreeMail);
+        }
+        // Patterns see the normalized address, so lowercase rules and the
+        // punycode domains built by the pattern form match any spelling.
+        if !self.pattern.is_match(&email) {
+            return Ok(Verdict::PatternMismatch);
+        }
+        let matched = mail_db::match_mail(&state.pool, self.panel_id, &email).await?;
+        if self.enable_check_mail && matched.is_empty() {
+            return Ok(Verdict::NotInList);
+        }
+        let mut entries = Vec::new();
+        let mut tags = Vec::new();
+        for (entry, entry_tags) in matched {
+            entries.push(entry);
+            for tag in entry_tags {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+
+        Ok(Verdict::Accepted {
+            normalized_email: email,
+            entries,
+            tags,
+        })
+    }
+}
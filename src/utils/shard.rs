@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use twilight_gateway::{ConnectionStatus, Shard};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ShardStatus {
+    pub shard_id: u64,
+    pub total: u64,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+    /// Unix time of the last event received by the shard.
+    pub last_event_at: u64,
+}
+
+/// Latest status of every shard run by this process.
+#[derive(Default)]
+pub struct ShardStatuses(RwLock<BTreeMap<u64, ShardStatus>>);
+
+impl ShardStatuses {
+    pub fn update(&self, shard: &Shard) {
+        let status = match shard.status() {
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Disconnected { .. } => "disconnected",
+            ConnectionStatus::FatallyClosed { .. } => "fatally_closed",
+            ConnectionStatus::Identifying => "identifying",
+            ConnectionStatus::Resuming => "resuming",
+        };
+        let last_event_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let id = shard.id();
+        self.0.write().unwrap().insert(
+            id.number(),
+            ShardStatus {
+                shard_id: id.number(),
+                total: id.total(),
+                status,
+                latency_ms: shard
+                    .latency()
+                    .recent()
+                    .first()
+                    .map(|latency| latency.as_millis()),
+                last_event_at,
+            },
+        );
+    }
+
+    pub fn all(&self) -> Vec<ShardStatus> {
+        self.0.read().unwrap().values().cloned().collect()
+    }
+}
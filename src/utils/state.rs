@@ -1,15 +1,28 @@
 use std::sync::Arc;
 
+use crate::utils::cache::DiscordCache;
+use crate::utils::pattern::PatternCache;
+use crate::utils::provider::DisposableDomains;
+use crate::utils::shard::ShardStatuses;
+
 use bb8_redis::{bb8::Pool, RedisConnectionManager};
 use sqlx::PgPool;
 use twilight_http::{client::InteractionClient, Client as HttpClient};
-use twilight_model::id::{marker::ApplicationMarker, Id};
+use twilight_model::id::{
+    marker::{ApplicationMarker, UserMarker},
+    Id,
+};
 
 pub struct AppState {
     pub pool: Arc<PgPool>,
     pub http: Arc<HttpClient>,
     pub redis: Arc<Pool<RedisConnectionManager>>,
     pub application_id: Id<ApplicationMarker>,
+    pub bot_user_id: Id<UserMarker>,
+    pub disposable_domains: DisposableDomains,
+    pub patterns: PatternCache,
+    pub shards: ShardStatuses,
+    pub cache: DiscordCache,
 }
 
 impl AppState {
@@ -25,19 +38,27 @@ impl AppState {
         let http = HttpClient::new(discord_token);
         let application = http.current_user_application().await?.model().await?;
         tracing::info!("Get application id: {}", application.id);
+        let bot_user = http.current_user().await?.model().await?;
 
         let manager = RedisConnectionManager::new(redis_uri)?;
         let redis = Pool::builder().build(manager).await?;
 
+        let disposable_domains = DisposableDomains::load()?;
+
         Ok(Self {
             pool: Arc::new(pool),
             http: Arc::new(http),
             redis: Arc::new(redis),
             application_id: application.id,
+            bot_user_id: bot_user.id,
+            disposable_domains,
+            patterns: PatternCache::default(),
+            shards: ShardStatuses::default(),
+            cache: DiscordCache::default(),
         })
     }
 
-    pub fn interaction(&self) -> InteractionClient {
+    pub fn interaction(&self) -> InteractionClient<'_> {
         self.http.interaction(self.application_id)
     }
 }